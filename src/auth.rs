@@ -0,0 +1,261 @@
+use clap::ValueEnum;
+use imap::Authenticator;
+use serde::Deserialize;
+
+use super::mishaps::Mishap;
+use super::settings::Settings;
+
+/// How we prove who we are to the IMAP server.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum AuthMethod {
+    /// Plain LOGIN with a password (or app password)
+    Password,
+    /// SASL XOAUTH2, as used by Gmail and Microsoft 365
+    Xoauth2,
+    /// SASL OAUTHBEARER (RFC 7628)
+    Oauthbearer,
+}
+
+pub enum Credentials {
+    Password(String),
+    OAuth {
+        method: AuthMethod,
+        access_token: String,
+    },
+}
+
+/// Work out the credentials to log in with, refreshing an OAuth access token if needed.
+pub async fn credentials(settings: &Settings) -> Result<Credentials, Mishap> {
+    match settings.imap_auth {
+        AuthMethod::Password => match &settings.imap_password {
+            Some(password) => Ok(Credentials::Password(password.clone())),
+            None => Err(Mishap::MissingSetting(String::from("--imap-password"))),
+        },
+        method => {
+            let access_token = refresh_access_token(settings).await?;
+            Ok(Credentials::OAuth {
+                method,
+                access_token,
+            })
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Exchange the stored refresh token for a new access token at the token endpoint.
+async fn refresh_access_token(settings: &Settings) -> Result<String, Mishap> {
+    let required = |value: &Option<String>, name: &str| {
+        value
+            .clone()
+            .ok_or_else(|| Mishap::MissingSetting(name.to_string()))
+    };
+
+    let params = [
+        ("grant_type", String::from("refresh_token")),
+        (
+            "refresh_token",
+            required(&settings.oauth_refresh_token, "--oauth-refresh-token")?,
+        ),
+        (
+            "client_id",
+            required(&settings.oauth_client_id, "--oauth-client-id")?,
+        ),
+        (
+            "client_secret",
+            required(&settings.oauth_client_secret, "--oauth-client-secret")?,
+        ),
+    ];
+
+    let res = reqwest::Client::new()
+        .post(&settings.oauth_token_url)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|err| Mishap::OAuth(err.to_string()))?
+        .text()
+        .await
+        .map_err(|err| Mishap::OAuth(err.to_string()))?;
+
+    let token: TokenResponse = serde_json::from_str(&res).map_err(|err| {
+        Mishap::OAuth(format!(
+            "unexpected response from token endpoint ({}): {}",
+            err,
+            res.trim()
+        ))
+    })?;
+
+    match token {
+        TokenResponse {
+            access_token: Some(access_token),
+            ..
+        } => Ok(access_token),
+        TokenResponse {
+            error,
+            error_description,
+            ..
+        } => Err(Mishap::OAuth(format!(
+            "token refresh failed: {} {}",
+            error.unwrap_or_default(),
+            error_description.unwrap_or_default()
+        ))),
+    }
+}
+
+/// SASL client for XOAUTH2 and OAUTHBEARER.
+pub struct OAuth2<'a> {
+    pub method: AuthMethod,
+    pub user: &'a str,
+    pub access_token: &'a str,
+}
+
+impl OAuth2<'_> {
+    pub fn mechanism(&self) -> &'static str {
+        match self.method {
+            AuthMethod::Oauthbearer => "OAUTHBEARER",
+            _ => "XOAUTH2",
+        }
+    }
+}
+
+impl Authenticator for OAuth2<'_> {
+    type Response = String;
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        // A non-empty challenge is the server describing an error.
+        // We must reply (with an empty or dummy message) to get the final NO response.
+        if !challenge.is_empty() {
+            log::error!("OAuth failed: {}", String::from_utf8_lossy(challenge));
+            return match self.method {
+                AuthMethod::Oauthbearer => String::from("\x01"),
+                _ => String::new(),
+            };
+        }
+
+        match self.method {
+            AuthMethod::Oauthbearer => format!(
+                "n,a={},\x01auth=Bearer {}\x01\x01",
+                self.user, self.access_token
+            ),
+            _ => format!(
+                "user={}\x01auth=Bearer {}\x01\x01",
+                self.user, self.access_token
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use pretty_assertions::assert_eq;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answer a single HTTP request with `body`, returning the request we received.
+    fn token_endpoint(body: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !String::from_utf8_lossy(&request).contains("grant_type") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        (url, handle)
+    }
+
+    fn oauth_settings(token_url: &str) -> Settings {
+        Settings::parse_from([
+            "dogpost",
+            "--imap-user=dog@example.com",
+            "--imap-auth=xoauth2",
+            "--oauth-client-id=client",
+            "--oauth-client-secret=secret",
+            "--oauth-refresh-token=refresh",
+            &format!("--oauth-token-url={}", token_url),
+            "--github-token=gh",
+            "--github-repo=user/repo",
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_refresh_access_token() {
+        let (url, server) = token_endpoint(r#"{"access_token":"ya29.new","expires_in":3599}"#);
+
+        let credentials = credentials(&oauth_settings(&url)).await.unwrap();
+        let request = server.join().unwrap();
+
+        assert!(request.contains("refresh_token=refresh"));
+        assert!(request.contains("client_id=client"));
+        match credentials {
+            Credentials::OAuth { access_token, .. } => assert_eq!("ya29.new", access_token),
+            Credentials::Password(_) => panic!("expected OAuth credentials"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rejected() {
+        let (url, _server) = token_endpoint(r#"{"error":"invalid_grant"}"#);
+
+        let result = credentials(&oauth_settings(&url)).await;
+        assert!(matches!(result, Err(Mishap::OAuth(msg)) if msg.contains("invalid_grant")));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_not_json() {
+        let (url, _server) = token_endpoint("<html>Service Unavailable</html>");
+
+        let result = credentials(&oauth_settings(&url)).await;
+        assert!(
+            matches!(result, Err(Mishap::OAuth(msg)) if msg.contains("<html>Service Unavailable</html>"))
+        );
+    }
+
+    #[test]
+    fn test_xoauth2_response() {
+        let auth = OAuth2 {
+            method: AuthMethod::Xoauth2,
+            user: "dog@example.com",
+            access_token: "abc",
+        };
+        assert_eq!("XOAUTH2", auth.mechanism());
+        assert_eq!(
+            "user=dog@example.com\x01auth=Bearer abc\x01\x01",
+            auth.process(b"")
+        );
+        assert_eq!("", auth.process(b"{\"status\":\"400\"}"));
+    }
+
+    #[test]
+    fn test_oauthbearer_response() {
+        let auth = OAuth2 {
+            method: AuthMethod::Oauthbearer,
+            user: "dog@example.com",
+            access_token: "abc",
+        };
+        assert_eq!("OAUTHBEARER", auth.mechanism());
+        assert_eq!(
+            "n,a=dog@example.com,\x01auth=Bearer abc\x01\x01",
+            auth.process(b"")
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use log::debug;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use super::dkim::{self, KeyLookup};
+use super::dns;
+use super::mishaps::Mishap;
+use super::settings::Settings;
+
+/// Check that a message really comes from the sender's domain, if asked to by the settings.
+///
+/// Either the receiving server's `Authentication-Results` (for a trusted authserv-id) must show
+/// DKIM or DMARC passing for the sender's domain, or one of the message's DKIM signatures
+/// from that domain must verify.
+pub fn check(settings: &Settings, message: &[u8], sender: &str) -> Result<(), Mishap> {
+    if settings.trusted_authserv_id.is_empty() && !settings.verify_dkim {
+        return Ok(());
+    }
+
+    let domain = sender
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .unwrap_or_default();
+    let mut reasons = Vec::new();
+
+    if !settings.trusted_authserv_id.is_empty() {
+        match authentication_results(message, &settings.trusted_authserv_id, &domain) {
+            Ok(()) => return Ok(()),
+            Err(reason) => reasons.push(reason),
+        }
+    }
+
+    if settings.verify_dkim {
+        let keys: Box<dyn KeyLookup> = match &settings.dkim_key_file {
+            Some(path) => Box::new(KeyFile::load(path)?),
+            None => Box::new(Dns {
+                server: settings.dns_server.map_or_else(dns::system_server, Ok)?,
+            }),
+        };
+
+        match dkim::verify(message, keys.as_ref()) {
+            Ok(domains) if domains.iter().any(|d| aligned(&domain, d)) => return Ok(()),
+            Ok(domains) => reasons.push(format!(
+                "DKIM signed by {}, not {}",
+                domains.join(", "),
+                domain
+            )),
+            Err(reason) => reasons.push(reason),
+        }
+    }
+
+    Err(Mishap::Unverified {
+        sender: sender.to_string(),
+        reason: reasons.join("; "),
+    })
+}
+
+/// The sender's domain is the signing domain, or a subdomain of it.
+fn aligned(sender_domain: &str, signing_domain: &str) -> bool {
+    let signing_domain = signing_domain.trim_start_matches('@').to_lowercase();
+    sender_domain == signing_domain || sender_domain.ends_with(&format!(".{}", signing_domain))
+}
+
+/// Check the topmost `Authentication-Results` header from a trusted server.
+///
+/// Lower ones may have been added by anyone before the message reached that server.
+fn authentication_results(message: &[u8], trusted: &[String], domain: &str) -> Result<(), String> {
+    let (headers, _) = mailparse::parse_headers(message).map_err(|err| err.to_string())?;
+
+    let results = headers
+        .iter()
+        .filter(|h| {
+            h.get_key_ref()
+                .eq_ignore_ascii_case("Authentication-Results")
+        })
+        .map(|h| without_comments(&h.get_value()))
+        .find(|value| {
+            let authserv_id = value.split(';').next().unwrap_or_default();
+            let authserv_id = authserv_id.split_whitespace().next().unwrap_or_default();
+            trusted.iter().any(|t| t.eq_ignore_ascii_case(authserv_id))
+        })
+        .ok_or_else(|| String::from("no Authentication-Results from a trusted server"))?;
+
+    debug!("Authentication-Results: {}", results);
+
+    for result in results.split(';').skip(1) {
+        let mut words = result.split_whitespace();
+        let Some((method, outcome)) = words.next().and_then(|w| w.split_once('=')) else {
+            continue;
+        };
+        let properties: BTreeMap<String, String> = words
+            .filter_map(|w| w.split_once('='))
+            .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+            .collect();
+
+        let property_aligned = |names: &[&str]| {
+            names.iter().any(|name| {
+                properties
+                    .get(*name)
+                    .is_some_and(|v| aligned(domain, v.rsplit('@').next().unwrap_or(v)))
+            })
+        };
+
+        let passed = outcome.eq_ignore_ascii_case("pass")
+            && match method.to_lowercase().as_str() {
+                "dmarc" => property_aligned(&["header.from"]),
+                "dkim" => property_aligned(&["header.d", "header.i"]),
+                _ => false,
+            };
+        if passed {
+            return Ok(());
+        }
+    }
+
+    Err(format!(
+        "Authentication-Results show no DKIM or DMARC pass for {}",
+        domain
+    ))
+}
+
+fn without_comments(value: &str) -> String {
+    let mut result = String::new();
+    let mut depth = 0;
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            c if depth == 0 => result.push(c),
+            _ => {}
+        }
+    }
+    result
+}
+
+struct Dns {
+    server: SocketAddr,
+}
+
+impl KeyLookup for Dns {
+    fn txt(&self, name: &str) -> Result<Option<String>, String> {
+        let records = dns::txt(self.server, name).map_err(|err| err.to_string())?;
+        Ok(records.into_iter().find(|r| r.contains("p=")))
+    }
+}
+
+/// DKIM keys from a file instead of DNS, e.g., for tests.
+/// Each line is a name and its TXT record: `selector._domainkey.example.com v=DKIM1; k=rsa; p=...`.
+struct KeyFile {
+    records: BTreeMap<String, String>,
+}
+
+impl KeyFile {
+    fn load(path: &Path) -> Result<KeyFile, Mishap> {
+        let text = std::fs::read_to_string(path)?;
+        let records = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(char::is_whitespace))
+            .map(|(name, record)| {
+                let name = name.trim_end_matches('.').to_lowercase();
+                (name, record.replace('"', "").trim().to_string())
+            })
+            .collect();
+        Ok(KeyFile { records })
+    }
+}
+
+impl KeyLookup for KeyFile {
+    fn txt(&self, name: &str) -> Result<Option<String>, String> {
+        Ok(self.records.get(&name.to_lowercase()).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dkim");
+
+    fn reason(result: Result<(), Mishap>) -> Option<String> {
+        match result {
+            Err(Mishap::Unverified { reason, .. }) => Some(reason),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(()) => None,
+        }
+    }
+
+    const RESULTS: &str = "Authentication-Results: mx.example.org;\r\n dkim=pass (2048-bit key) header.i=@example.com header.s=sel;\r\n spf=pass smtp.mailfrom=example.com;\r\n dmarc=pass (p=NONE) header.from=example.com\r\nAuthentication-Results: mx.example.org; dkim=fail header.d=example.com\r\nFrom: alice@example.com\r\n\r\nHello\r\n";
+
+    #[test]
+    fn test_not_configured() {
+        assert_eq!(
+            None,
+            reason(check(&Settings::for_tests(&[]), b"", "alice@example.com"))
+        );
+    }
+
+    #[test]
+    fn test_trusted_authentication_results() {
+        let settings = Settings::for_tests(&["--trusted-authserv-id", "mx.example.org"]);
+        assert_eq!(
+            None,
+            reason(check(&settings, RESULTS.as_bytes(), "alice@example.com"))
+        );
+        assert_eq!(
+            Some(String::from(
+                "Authentication-Results show no DKIM or DMARC pass for evil.com"
+            )),
+            reason(check(&settings, RESULTS.as_bytes(), "alice@evil.com"))
+        );
+    }
+
+    #[test]
+    fn test_untrusted_authentication_results() {
+        let settings = Settings::for_tests(&["--trusted-authserv-id", "mx.google.com"]);
+        assert_eq!(
+            Some(String::from(
+                "no Authentication-Results from a trusted server"
+            )),
+            reason(check(&settings, RESULTS.as_bytes(), "alice@example.com"))
+        );
+    }
+
+    #[test]
+    fn test_only_topmost_trusted_results_count() {
+        let forged = format!(
+            "Authentication-Results: mx.example.org; dkim=fail header.d=example.com\r\n{}",
+            RESULTS
+        );
+        let settings = Settings::for_tests(&["--trusted-authserv-id", "mx.example.org"]);
+        assert!(reason(check(&settings, forged.as_bytes(), "alice@example.com")).is_some());
+    }
+
+    #[test]
+    fn test_dkim_with_key_file() {
+        let key_file = format!("{}/keys.txt", FIXTURES);
+        let settings = Settings::for_tests(&["--verify-dkim", "--dkim-key-file", &key_file]);
+        let message = std::fs::read(format!("{}/relaxed.eml", FIXTURES)).unwrap();
+
+        assert_eq!(
+            None,
+            reason(check(&settings, &message, "alice@example.com"))
+        );
+        assert_eq!(
+            None,
+            reason(check(&settings, &message, "alice@mail.example.com"))
+        );
+        assert_eq!(
+            Some(String::from("DKIM signed by example.com, not example.net")),
+            reason(check(&settings, &message, "alice@example.net"))
+        );
+    }
+
+    #[test]
+    fn test_dkim_with_dns() {
+        let keys = std::fs::read_to_string(format!("{}/keys.txt", FIXTURES)).unwrap();
+        let (name, record) = keys.lines().last().unwrap().split_once(' ').unwrap();
+        // TXT strings are at most 255 bytes, so a long key is split across several
+        let strings: Vec<String> = record
+            .as_bytes()
+            .chunks(255)
+            .map(|c| String::from_utf8(c.to_vec()).unwrap())
+            .collect();
+        let server = dns::stub::serve(vec![(name.to_string(), strings)]).to_string();
+
+        let settings = Settings::for_tests(&["--verify-dkim", "--dns-server", &server]);
+        let message = std::fs::read(format!("{}/simple.eml", FIXTURES)).unwrap();
+        assert_eq!(
+            None,
+            reason(check(&settings, &message, "alice@example.com"))
+        );
+    }
+}
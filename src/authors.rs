@@ -0,0 +1,157 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use super::mishaps::Mishap;
+
+/// The people who post, keyed by their slug (used for the `authors` taxonomy).
+///
+/// ```yaml
+/// jane:
+///   name: Jane Smith
+///   emails: [jane@example.com, mum@example.net]
+///   avatar: /img/jane.jpg
+///   bio: Walks Rex most mornings.
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Directory {
+    authors: BTreeMap<String, Author>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Author {
+    pub name: String,
+    pub emails: Vec<String>,
+    pub avatar: Option<String>,
+    pub bio: Option<String>,
+}
+
+/// An author, as found in the directory.
+#[derive(Debug, PartialEq)]
+pub struct Profile<'a> {
+    pub slug: &'a str,
+    pub author: &'a Author,
+}
+
+impl Directory {
+    pub fn load(path: &Path) -> Result<Directory, Mishap> {
+        Directory::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(yaml: &str) -> Result<Directory, Mishap> {
+        let directory: Directory =
+            serde_yaml::from_str(yaml).map_err(|err| Mishap::Authors(err.to_string()))?;
+
+        // An address can only belong to one person
+        let mut seen: BTreeMap<String, &str> = BTreeMap::new();
+        for (slug, author) in directory.authors.iter() {
+            for email in author.emails.iter() {
+                if let Some(other) = seen.insert(email.trim().to_lowercase(), slug) {
+                    return Err(Mishap::Authors(format!(
+                        "{} is listed for both {} and {}",
+                        email, other, slug
+                    )));
+                }
+            }
+        }
+
+        Ok(directory)
+    }
+
+    /// The author with the given email address, ignoring case.
+    pub fn find(&self, address: &str) -> Option<Profile<'_>> {
+        let address = address.trim();
+        self.authors
+            .iter()
+            .find(|(_, author)| {
+                author
+                    .emails
+                    .iter()
+                    .any(|email| email.trim().eq_ignore_ascii_case(address))
+            })
+            .map(|(slug, author)| Profile { slug, author })
+    }
+}
+
+impl Profile<'_> {
+    /// Front matter for the post: the `authors` taxonomy, and the avatar and bio if known.
+    pub fn fields(&self) -> BTreeMap<String, serde_yaml::Value> {
+        let mut fields = BTreeMap::new();
+        fields.insert(
+            String::from("authors"),
+            serde_yaml::Value::Sequence(vec![self.slug.into()]),
+        );
+        if let Some(avatar) = &self.author.avatar {
+            fields.insert(String::from("author_avatar"), avatar.as_str().into());
+        }
+        if let Some(bio) = &self.author.bio {
+            fields.insert(String::from("author_bio"), bio.as_str().into());
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const YAML: &str = "
+jane:
+  name: Jane Smith
+  emails: [jane@example.com, Mum@Example.net]
+  avatar: /img/jane.jpg
+  bio: Walks Rex most mornings.
+bob:
+  name: Bob
+  emails:
+    - bob@example.com
+";
+
+    #[test]
+    fn test_find() {
+        let directory = Directory::parse(YAML).unwrap();
+
+        let jane = directory.find("mum@example.NET").unwrap();
+        assert_eq!("jane", jane.slug);
+        assert_eq!("Jane Smith", jane.author.name);
+
+        assert_eq!(
+            Some("bob"),
+            directory.find("bob@example.com").map(|p| p.slug)
+        );
+        assert_eq!(None, directory.find("rex@example.com"));
+    }
+
+    #[test]
+    fn test_fields() {
+        let directory = Directory::parse(YAML).unwrap();
+
+        let jane = directory.find("jane@example.com").unwrap().fields();
+        assert_eq!(
+            "author_avatar: /img/jane.jpg\nauthor_bio: Walks Rex most mornings.\nauthors:\n- jane\n",
+            serde_yaml::to_string(&jane).unwrap()
+        );
+
+        let bob = directory.find("bob@example.com").unwrap().fields();
+        assert_eq!("authors:\n- bob\n", serde_yaml::to_string(&bob).unwrap());
+    }
+
+    #[test]
+    fn test_bad_directory() {
+        for yaml in [
+            "jane:\n  name: Jane\n",
+            "jane:\n  name: Jane\n  emails: [jane@example.com]\n  twitter: jane\n",
+            "jane:\n  name: Jane\n  emails: [jane@example.com]\nbob:\n  name: Bob\n  emails: [JANE@example.com]\n",
+        ] {
+            assert!(
+                matches!(Directory::parse(yaml), Err(Mishap::Authors(_))),
+                "{}",
+                yaml
+            );
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use log::info;
+use tempfile::TempDir;
+
+use super::authors::Directory;
+use super::blog;
+use super::email;
+use super::github::{Github, NewContent};
+use super::media;
+use super::mishaps::Mishap;
+use super::settings::Settings;
+use super::sources::Source;
+use super::state::State;
+
+/// The result of turning one message into a post: the post title, or why it failed.
+pub type Outcome = Result<String, Mishap>;
+
+/// Post every new message from the source, recording progress in `state` as we go.
+///
+/// Each message becomes its own post. A failure is reported in the outcomes, but doesn't stop the others.
+/// A dry run leaves the source and state as they were, so a real run afterwards sees the same messages.
+/// An `Err` means we lost access to the source or state file part way through.
+pub async fn process(
+    settings: &Settings,
+    gh: &Github,
+    directory: &Directory,
+    source: &mut dyn Source,
+    state: &mut State,
+) -> Result<Vec<Outcome>, Mishap> {
+    let messages = source.fetch(state, settings.limit)?;
+
+    let mut outcomes = Vec::new();
+    for message in messages.iter() {
+        let message_id = email::message_id(&message.mime);
+
+        let posted = if let Some(id) = message_id.as_deref().filter(|id| state.already_posted(id)) {
+            info!("Skipping {}: already posted", id);
+            true
+        } else {
+            let outcome = post(settings, gh, directory, &message.mime).await;
+
+            if let (Ok(_), Some(id), false) = (&outcome, &message_id, settings.dry_run) {
+                state.record_posted(id);
+            }
+
+            let posted = outcome.is_ok();
+            outcomes.push(outcome);
+            posted
+        };
+
+        // Save first, so a post is never made twice because moving the message failed
+        if !settings.dry_run {
+            state.record(&message.origin, posted);
+            state.save(&settings.state_file)?;
+            source.done(message, posted)?;
+        }
+    }
+
+    Ok(outcomes)
+}
+
+/// Turn a single MIME message into a blog post and commit it, returning the post title.
+async fn post(
+    settings: &Settings,
+    gh: &Github,
+    directory: &Directory,
+    mime_message: &[u8],
+) -> Outcome {
+    let working_dir = TempDir::new()?;
+    let extract = |msg| email::extract(settings, directory, working_dir.path(), msg);
+
+    let info = email::parse(mime_message)
+        .and_then(extract)
+        .and_then(media::transcode)?;
+
+    let markdown = blog::write(&info)?;
+
+    let commit_msg = format!("add post: {}", info.title);
+
+    let mut contents: Vec<NewContent> = info
+        .attachments
+        .iter()
+        .map(|a| NewContent::path(&a.github_path, &a.file_path))
+        .collect();
+    contents.push(NewContent::text(&info.file_path, &markdown));
+
+    if settings.dry_run {
+        dbg!(&info);
+        dbg!(&contents);
+    } else {
+        gh.commit(&commit_msg, &contents)
+            .await
+            .map_err(|err| Mishap::Commit(err.to_string()))?;
+    }
+
+    Ok(info.title)
+}
+
+pub fn summarise(outcomes: &[Outcome]) {
+    for (count, outcome) in outcomes.iter().enumerate() {
+        match outcome {
+            Ok(title) => println!("Message {}: posted: {}", count + 1, title),
+            Err(err) => eprintln!("Message {}: Failed: {}", count + 1, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::{Message, Origin};
+    use pretty_assertions::assert_eq;
+
+    /// IMAP-like messages held in memory, remembering what was acknowledged.
+    struct Inbox {
+        messages: Vec<(u32, &'static str)>,
+        done: Vec<(u32, bool)>,
+        broken: bool,
+    }
+
+    impl Source for Inbox {
+        fn fetch(
+            &mut self,
+            state: &mut State,
+            _limit: Option<u32>,
+        ) -> Result<Vec<Message>, Mishap> {
+            Ok(self
+                .messages
+                .iter()
+                .filter(|(uid, _)| *uid > state.last_uid || state.failed_uids.contains(uid))
+                .map(|(uid, mime)| Message {
+                    origin: Origin::Imap(*uid),
+                    mime: mime.as_bytes().to_vec(),
+                })
+                .collect())
+        }
+
+        fn done(&mut self, message: &Message, posted: bool) -> Result<(), Mishap> {
+            if self.broken {
+                return Err(Mishap::File(std::io::Error::other("move failed")));
+            }
+            if let Origin::Imap(uid) = message.origin {
+                self.done.push((uid, posted));
+            }
+            Ok(())
+        }
+    }
+
+    /// A message from a sender who isn't allowed, and one that was posted before,
+    /// so neither needs a commit to Github.
+    fn inbox() -> Inbox {
+        Inbox {
+            messages: vec![
+                (1, "From: rex@example.org\r\nSubject: Walk\r\n\r\nHello"),
+                (
+                    2,
+                    concat!(
+                        "From: rex@example.com\r\nMessage-ID: <park@example.com>\r\n",
+                        "Subject: Park\r\n\r\nHello"
+                    ),
+                ),
+            ],
+            done: Vec::new(),
+            broken: false,
+        }
+    }
+
+    fn settings(state_file: &std::path::Path, extra: &[&str]) -> Settings {
+        let mut args = vec![
+            "--allowed-domains",
+            "example.com",
+            "--state-file",
+            state_file.to_str().unwrap(),
+        ];
+        args.extend(extra);
+        Settings::for_tests(&args)
+    }
+
+    #[tokio::test]
+    async fn test_failed_messages_are_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("state.json");
+        let settings = settings(&state_file, &[]);
+        let gh = Github::new("t", "r", "main");
+        let directory = Directory::default();
+        let mut inbox = inbox();
+        let mut state = State::default();
+        state.record_posted("<park@example.com>");
+
+        let outcomes = process(&settings, &gh, &directory, &mut inbox, &mut state)
+            .await
+            .unwrap();
+        assert_eq!(1, outcomes.len());
+        assert!(outcomes[0].is_err());
+        assert_eq!(vec![(1, false), (2, true)], inbox.done);
+        assert_eq!(2, state.last_uid);
+
+        // The failed message is fetched again, and the saved state agrees
+        let outcomes = process(&settings, &gh, &directory, &mut inbox, &mut state)
+            .await
+            .unwrap();
+        assert_eq!(1, outcomes.len());
+        assert_eq!(
+            vec![1],
+            Vec::from_iter(State::load(&state_file).unwrap().failed_uids)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_leaves_messages_for_a_real_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("state.json");
+        let settings = settings(&state_file, &["--dry-run"]);
+        let gh = Github::new("t", "r", "main");
+        let directory = Directory::default();
+        let mut inbox = inbox();
+        let mut state = State::default();
+        state.record_posted("<park@example.com>");
+
+        process(&settings, &gh, &directory, &mut inbox, &mut state)
+            .await
+            .unwrap();
+        assert!(inbox.done.is_empty());
+        assert!(!state_file.exists());
+
+        let fetched = inbox.fetch(&mut state, None).unwrap();
+        assert_eq!(2, fetched.len());
+    }
+
+    #[tokio::test]
+    async fn test_state_saved_before_moving_the_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("state.json");
+        let settings = settings(&state_file, &[]);
+        let gh = Github::new("t", "r", "main");
+        let directory = Directory::default();
+        let mut inbox = inbox();
+        inbox.messages.remove(0);
+        inbox.broken = true;
+        let mut state = State::default();
+        state.record_posted("<park@example.com>");
+
+        let result = process(&settings, &gh, &directory, &mut inbox, &mut state).await;
+        assert!(result.is_err());
+
+        let saved = State::load(&state_file).unwrap();
+        assert!(saved.already_posted("<park@example.com>"));
+        assert_eq!(2, saved.last_uid);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::tag::Tag;
 
+use super::embed;
 use super::mishaps::Mishap;
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::io::Write;
 use std::path::PathBuf;
@@ -17,6 +20,13 @@ pub struct PostInfo {
     pub attachments: Vec<Attachment>,
     pub file_path: String,
     pub tags: Vec<Tag>,
+    pub message_id: Option<String>,
+    pub summary: Option<String>,
+    pub draft: bool,
+    // Use the first attachment as the post's image:
+    pub feature_image: bool,
+    // Other front matter fields, given in the message body:
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 impl PostInfo {
@@ -37,27 +47,27 @@ impl PostInfo {
             date,
             attachments,
             file_path,
+            message_id: None,
+            summary: None,
+            draft: false,
+            feature_image: true,
+            extra: BTreeMap::new(),
         }
     }
 
-    pub fn map_attachments<F>(self, f: F) -> Result<PostInfo, Mishap>
+    pub fn map_attachments<F>(mut self, f: F) -> Result<PostInfo, Mishap>
     where
         F: Fn(Attachment) -> Result<Attachment, Mishap>,
     {
         let mut mapped_attachments = Vec::new();
-        for attachment in self.attachments.into_iter() {
+        for attachment in std::mem::take(&mut self.attachments).into_iter() {
             let mapped_attachment = f(attachment)?;
             mapped_attachments.push(mapped_attachment);
         }
 
         Ok(PostInfo {
-            title: self.title,
-            author: self.author,
-            content: self.content,
-            date: self.date,
             attachments: mapped_attachments,
-            file_path: self.file_path,
-            tags: self.tags,
+            ..self
         })
     }
 }
@@ -74,6 +84,9 @@ pub struct Attachment {
     pub github_path: String,
 
     pub mime_type: String,
+
+    // The part's Content-ID, used to place the attachment in the body:
+    pub content_id: String,
 }
 
 impl Attachment {
@@ -112,6 +125,18 @@ struct FrontMatter {
     tags: Vec<Tag>,
 
     count: Count,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    draft: bool,
+
+    #[serde(flatten)]
+    extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -125,12 +150,18 @@ pub fn write(post: &PostInfo) -> Result<String, Mishap> {
     write!(markdown, "{}", post_meta(post))?;
     write!(markdown, "\n\n")?;
 
-    match &post.content {
-        Some(text) => write!(markdown, "{}\n\n", text)?,
-        None => {}
-    };
+    let mut placed = Vec::new();
+    if let Some(text) = &post.content {
+        let (text, inline) = place_attachments(text, &post.attachments);
+        write!(markdown, "{}\n\n", embed::link(text.trim()))?;
+        placed = inline;
+    }
 
-    for media in post.attachments.iter() {
+    // Attachments not referenced from the body follow the text
+    for (index, media) in post.attachments.iter().enumerate() {
+        if placed.contains(&index) {
+            continue;
+        }
         if let Some(media_md) = media.markdown() {
             write!(markdown, "{media_md}")?;
             write!(markdown, "\n\n")?;
@@ -140,8 +171,41 @@ pub fn write(post: &PostInfo) -> Result<String, Mishap> {
     Ok(String::from_utf8(markdown)?)
 }
 
+/// Replace `cid:` references in the text with the Markdown for the matching attachment.
+///
+/// HTML bodies reference images as `![alt](cid:ID)`, and Outlook's plain text as `[cid:ID]`.
+/// Returns the text, and the indexes of the attachments that were placed.
+fn place_attachments(text: &str, attachments: &[Attachment]) -> (String, Vec<usize>) {
+    let pattern = Regex::new(r"!\[[^\]]*\]\(cid:([^)\s]+)\)|\[cid:([^\]\s]+)\]").unwrap();
+    let mut placed = Vec::new();
+
+    let text = pattern.replace_all(text, |caps: &Captures| {
+        let content_id = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        let found = attachments
+            .iter()
+            .enumerate()
+            .find(|(_, a)| a.content_id == content_id);
+
+        match found.and_then(|(index, a)| a.markdown().map(|md| (index, md))) {
+            Some((index, media_md)) => {
+                placed.push(index);
+                media_md
+            }
+            // A reference to something we don't have would only be a broken image
+            None => String::new(),
+        }
+    });
+
+    (text.to_string(), placed)
+}
+
 fn post_meta(post: &PostInfo) -> String {
-    let featured_image = post.attachments.first().map(|img| &img.url_path).cloned();
+    let featured_image = post
+        .attachments
+        .first()
+        .filter(|_| post.feature_image)
+        .map(|img| &img.url_path)
+        .cloned();
 
     let count = Count {
         images: post.attachments.iter().filter(|a| a.is_image()).count(),
@@ -156,9 +220,72 @@ fn post_meta(post: &PostInfo) -> String {
         post_type: "post".to_string(),
         tags: post.tags.clone(),
         count,
+        message_id: post.message_id.clone(),
+        summary: post.summary.clone(),
+        draft: post.draft,
+        extra: post.extra.clone(),
     };
 
     let yaml = serde_yaml::to_string(&fm).unwrap();
 
     format!("---\n{}\n---", yaml)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn image(content_id: &str, url_path: &str) -> Attachment {
+        Attachment {
+            file_path: PathBuf::from("0.jpg"),
+            url_path: url_path.to_string(),
+            github_path: url_path.to_string(),
+            mime_type: String::from("image/jpeg"),
+            content_id: content_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_place_attachments() {
+        let attachments = vec![
+            image("beach@example.com", "/media/beach.jpg"),
+            image("asleep@example.com", "/media/asleep.jpg"),
+            image("dogpost-part-2", "/media/extra.jpg"),
+        ];
+        let text = "Rex at the beach\n\n![IMG\\_1.jpg](cid:beach@example.com)\n\nand asleep [cid:asleep@example.com] ![](cid:missing)";
+
+        assert_eq!(
+            (
+                String::from(
+                    "Rex at the beach\n\n![](/media/beach.jpg)\n\nand asleep ![](/media/asleep.jpg) "
+                ),
+                vec![0, 1]
+            ),
+            place_attachments(text, &attachments)
+        );
+    }
+
+    #[test]
+    fn test_front_matter_from_body() {
+        let post = PostInfo {
+            summary: Some(String::from("Sand everywhere")),
+            draft: true,
+            extra: BTreeMap::from([(String::from("mood"), serde_yaml::Value::from("happy"))]),
+            ..PostInfo::new(
+                String::from("Beach"),
+                String::from("Jane"),
+                None,
+                OffsetDateTime::UNIX_EPOCH,
+                vec![],
+                vec![],
+                String::from("content/posts/beach.md"),
+            )
+        };
+
+        assert_eq!(
+            "---\ntitle: Beach\nauthor: Jane\ndate: 1970-01-01T00:00:00.000000000Z\ntype: post\ntags: []\ncount:\n  images: 0\n  videos: 0\nsummary: Sand everywhere\ndraft: true\nmood: happy\n\n---",
+            post_meta(&post)
+        );
+    }
+}
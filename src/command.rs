@@ -0,0 +1,126 @@
+use time::OffsetDateTime;
+
+use super::mishaps::Mishap;
+use super::preamble;
+
+/// Listed in `--help`.
+pub const HELP: &str = "Subject commands:
+  [draft]             Post as a draft
+  [private]           Publish, but leave the post out of lists (Hugo's build.list: never)
+  [pin]               Pin the post (pinned: true in the front matter)
+  [nofeature]         Don't use the first attachment as the post's featured image
+  [date:2026-10-01]   Backdate the post (also accepts \"2026-10-01 14:30\")";
+
+/// An instruction given in square brackets in the subject line.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Command {
+    Draft,
+    Private,
+    Pin,
+    NoFeature,
+    Date(OffsetDateTime),
+}
+
+/// Pull commands like `[draft]` out of a subject, as `tag::detag` does for hashtags.
+///
+/// Other bracketed text, such as a mailing list's `[family]`, is left in place.
+pub fn decommand(subject: &str) -> Result<(String, Vec<Command>), Mishap> {
+    let mut result = String::new();
+    let mut commands = Vec::new();
+    let mut rest = subject;
+
+    while let Some(start) = rest.find('[') {
+        let Some(len) = rest[start..].find(']') else {
+            break;
+        };
+        let token = &rest[start + 1..start + len];
+
+        result.push_str(&rest[..start]);
+        match command(token)? {
+            Some(cmd) => commands.push(cmd),
+            None => result.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+
+    let result = result.split_whitespace().collect::<Vec<_>>().join(" ");
+    Ok((result, commands))
+}
+
+fn command(token: &str) -> Result<Option<Command>, Mishap> {
+    let token = token.trim();
+    match token.to_lowercase().as_str() {
+        "draft" => Ok(Some(Command::Draft)),
+        "private" => Ok(Some(Command::Private)),
+        "pin" => Ok(Some(Command::Pin)),
+        "nofeature" => Ok(Some(Command::NoFeature)),
+        lower if lower.starts_with("date:") => {
+            let date = preamble::date_parse(token[5..].trim())
+                .map_err(|_| Mishap::EmailField(format!("subject command [{}]", token)))?;
+            Ok(Some(Command::Date(date)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_decommand_none() {
+        let subject = "Walk in the park";
+        assert_eq!(
+            decommand(subject).unwrap(),
+            (subject.to_string(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_decommand_flags() {
+        let subject = "[draft] Walk in the [PIN] park [nofeature] [private]";
+        let expected_result = "Walk in the park".to_string();
+        let expected_commands = vec![
+            Command::Draft,
+            Command::Pin,
+            Command::NoFeature,
+            Command::Private,
+        ];
+        assert_eq!(
+            decommand(subject).unwrap(),
+            (expected_result, expected_commands)
+        );
+    }
+
+    #[test]
+    fn test_decommand_date() {
+        let subject = "Walk in the park [date:2026-10-01]";
+        let expected_commands = vec![Command::Date(datetime!(2026-10-01 0:00 UTC))];
+        assert_eq!(
+            decommand(subject).unwrap(),
+            ("Walk in the park".to_string(), expected_commands)
+        );
+    }
+
+    #[test]
+    fn test_decommand_bad_date() {
+        assert!(matches!(
+            decommand("Walk [date:someday]"),
+            Err(Mishap::EmailField(_))
+        ));
+    }
+
+    #[test]
+    fn test_decommand_keeps_other_brackets() {
+        let subject = "Re: [family] Walk [draft] #park [unclosed";
+        assert_eq!(
+            decommand(subject).unwrap(),
+            (
+                "Re: [family] Walk #park [unclosed".to_string(),
+                vec![Command::Draft]
+            )
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use clap::ValueEnum;
+use imap::{Client, Connection};
+use log::debug;
+use rustls_connector::RustlsConnector;
+use rustls_connector::rustls::pki_types::CertificateDer;
+use rustls_connector::rustls::pki_types::pem::PemObject;
+use rustls_connector::rustls::{ClientConfig, RootCertStore};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpStream};
+use std::path::PathBuf;
+
+use super::mishaps::Mishap;
+
+/// How the connection to the mail server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Security {
+    /// TLS from the start (e.g., port 993)
+    Tls,
+    /// Plain connection upgraded with STARTTLS (e.g., port 143)
+    Starttls,
+    /// No encryption at all. Only allowed when connecting to localhost.
+    Plain,
+}
+
+/// Open an unauthenticated IMAP client.
+pub fn connect(
+    hostname: &str,
+    port: u16,
+    security: Security,
+    ca_certs: &[PathBuf],
+) -> Result<Client<Connection>, Mishap> {
+    debug!("Connecting to {}:{} using {:?}", hostname, port, security);
+
+    ensure_secure(hostname, security)?;
+
+    let tcp = TcpStream::connect((hostname, port))?;
+
+    let stream: Connection = match security {
+        Security::Plain => Box::new(tcp),
+        Security::Tls => Box::new(tls(hostname, tcp, ca_certs)?),
+        Security::Starttls => Box::new(tls(hostname, starttls(tcp)?, ca_certs)?),
+    };
+
+    let mut client = Client::new(stream);
+    if security == Security::Starttls {
+        // The greeting came before the upgrade, and isn't repeated
+        client.greeting_read = true;
+    } else {
+        client.read_greeting()?;
+    }
+
+    Ok(client)
+}
+
+/// Refuse to send credentials in the clear anywhere but localhost.
+pub fn ensure_secure(hostname: &str, security: Security) -> Result<(), Mishap> {
+    if security == Security::Plain && !is_localhost(hostname) {
+        Err(Mishap::Insecure(hostname.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Wrap a TCP connection in TLS, trusting the system certificates plus any `ca_certs`.
+pub fn tls(
+    hostname: &str,
+    tcp: TcpStream,
+    ca_certs: &[PathBuf],
+) -> Result<rustls_connector::TlsStream<TcpStream>, Mishap> {
+    let connector: RustlsConnector = tls_config(ca_certs)?.into();
+    let stream = connector
+        .connect(hostname, tcp)
+        .map_err(imap::error::Error::from)?;
+    Ok(stream)
+}
+
+/// Rustls configuration trusting the system certificates and the given PEM files.
+pub fn tls_config(ca_certs: &[PathBuf]) -> Result<ClientConfig, Mishap> {
+    let mut roots = RootCertStore::empty();
+
+    let (_added, _ignored) = roots.add_parsable_certificates(
+        rustls_connector::rustls_native_certs::load_native_certs().unwrap_or_default(),
+    );
+
+    for path in ca_certs {
+        debug!("Trusting certificates in {}", path.display());
+        let certs = CertificateDer::pem_file_iter(path)
+            .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| Mishap::Certificate(format!("{}: {}", path.display(), err)))?;
+
+        for cert in certs {
+            roots
+                .add(cert)
+                .map_err(|err| Mishap::Certificate(format!("{}: {}", path.display(), err)))?;
+        }
+    }
+
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// Read the server greeting and ask to upgrade to TLS (RFC 3501 section 6.2.1).
+fn starttls(tcp: TcpStream) -> Result<TcpStream, Mishap> {
+    let mut reader = BufReader::new(&tcp);
+
+    let mut greeting = String::new();
+    reader.read_line(&mut greeting)?;
+    debug!("Greeting: {}", greeting.trim_end());
+
+    (&tcp).write_all(b"dp0 STARTTLS\r\n")?;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(imap::error::Error::ConnectionLost.into());
+        }
+        if let Some(status) = line.strip_prefix("dp0 ") {
+            return if status.starts_with("OK") {
+                Ok(tcp)
+            } else {
+                Err(imap::error::Error::StartTlsNotAvailable.into())
+            };
+        }
+    }
+}
+
+fn is_localhost(hostname: &str) -> bool {
+    hostname.eq_ignore_ascii_case("localhost")
+        || hostname
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls_connector::rustls::pki_types::PrivateKeyDer;
+    use rustls_connector::rustls::{ServerConfig, ServerConnection, StreamOwned};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+    /// A TLS server, using a certificate signed by our test CA, that sends an IMAP greeting.
+    fn greeting_server() -> u16 {
+        let certs = CertificateDer::pem_file_iter(format!("{}/server.pem", FIXTURES))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_file(format!("{}/server.key", FIXTURES)).unwrap();
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut tls = StreamOwned::new(conn, tcp);
+            let _ = tls.write_all(b"* OK IMAP4rev1 ready\r\n");
+            let _ = tls.flush();
+        });
+
+        port
+    }
+
+    #[test]
+    fn test_trusts_extra_ca() {
+        let port = greeting_server();
+        let ca = PathBuf::from(format!("{}/ca.pem", FIXTURES));
+        assert!(connect("localhost", port, Security::Tls, &[ca]).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_unknown_issuer() {
+        let port = greeting_server();
+        assert!(connect("localhost", port, Security::Tls, &[]).is_err());
+    }
+
+    #[test]
+    fn test_plaintext_only_to_localhost() {
+        assert!(matches!(
+            connect("imap.example.com", 143, Security::Plain, &[]),
+            Err(Mishap::Insecure(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_localhost() {
+        assert!(is_localhost("localhost"));
+        assert!(is_localhost("127.0.0.1"));
+        assert!(is_localhost("::1"));
+        assert!(!is_localhost("imap.gmail.com"));
+        assert!(!is_localhost("10.0.0.1"));
+    }
+}
@@ -0,0 +1,182 @@
+use log::{error, info, warn};
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+
+use super::auth;
+use super::authors::Directory;
+use super::batch;
+use super::github::Github;
+use super::mailbox::Mailbox;
+use super::mishaps::Mishap;
+use super::settings::Settings;
+use super::sources::Source;
+use super::state::State;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait before reconnecting, doubling after each failure.
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Backoff {
+        Backoff { delay: MIN_BACKOFF }
+    }
+
+    /// The delay to wait now, growing the delay for next time.
+    fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (delay * 2).min(MAX_BACKOFF);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.delay = MIN_BACKOFF;
+    }
+}
+
+/// Keep the IMAP session open, posting messages as they arrive, until SIGTERM or SIGINT.
+///
+/// Connection failures, and errors once connected, are retried with exponential backoff.
+/// The backoff is only reset once a connection has processed the mailbox, so a failure
+/// straight after connecting doesn't retry in a tight loop.
+/// A shutdown request is honoured between messages, so a post is never left half-committed.
+pub async fn run(settings: &Settings, gh: &Github, directory: &Directory, mut state: State) {
+    let shutdown = shutdown_signal();
+    let mut backoff = Backoff::new();
+
+    while !*shutdown.borrow() {
+        let connection = match auth::credentials(settings).await {
+            Ok(credentials) => Mailbox::open(settings, &credentials),
+            Err(err) => Err(err),
+        };
+
+        let mailbox = match connection {
+            Ok(mailbox) => mailbox,
+            Err(err) => {
+                let delay = backoff.next();
+                warn!("Unable to connect, retrying in {:?}: {}", delay, err);
+                if !pause(delay, shutdown.clone()).await {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        info!("Connected to {}", &settings.imap_hostname);
+
+        if let Err(err) = watch_mailbox(
+            settings,
+            gh,
+            directory,
+            mailbox,
+            &mut state,
+            &mut backoff,
+            shutdown.clone(),
+        )
+        .await
+        {
+            let delay = backoff.next();
+            error!("Lost mailbox connection, retrying in {:?}: {}", delay, err);
+            if !pause(delay, shutdown.clone()).await {
+                break;
+            }
+        }
+    }
+
+    info!("Shutting down");
+}
+
+/// Process messages until the connection fails (`Err`) or we are asked to stop (`Ok`).
+async fn watch_mailbox(
+    settings: &Settings,
+    gh: &Github,
+    directory: &Directory,
+    mut mailbox: Mailbox,
+    state: &mut State,
+    backoff: &mut Backoff,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), Mishap> {
+    let poll_interval = Duration::from_secs(settings.poll_interval);
+    loop {
+        let outcomes = batch::process(settings, gh, directory, &mut mailbox, state).await?;
+        batch::summarise(&outcomes);
+        backoff.reset();
+
+        if *shutdown.borrow() {
+            return mailbox.close();
+        }
+
+        // IMAP access is blocking, so wait for new mail on another thread
+        let waiting = tokio::task::spawn_blocking(move || {
+            let outcome = mailbox.wait(poll_interval);
+            (mailbox, outcome)
+        });
+
+        tokio::select! {
+            joined = waiting => {
+                let (returned, outcome) = joined.expect("mailbox wait panicked");
+                outcome?;
+                mailbox = returned;
+            }
+            _ = shutdown.changed() => {
+                // Nothing is in progress while we wait, so we can abandon the connection.
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Sleep for `duration`, returning `false` if we were asked to shut down in the meantime.
+async fn pause(duration: Duration, mut shutdown: watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => true,
+        _ = shutdown.changed() => false,
+    }
+}
+
+fn shutdown_signal() -> watch::Receiver<bool> {
+    let (tx, rx) = watch::channel(false);
+
+    let mut terminate = signal(SignalKind::terminate()).expect("installing SIGTERM handler");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("installing SIGINT handler");
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM"),
+            _ = interrupt.recv() => info!("Received SIGINT"),
+        }
+        let _ = tx.send(true);
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_backoff_grows_to_a_limit() {
+        let mut backoff = Backoff::new();
+        let delays: Vec<u64> = (0..11).map(|_| backoff.next().as_secs()).collect();
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300], delays);
+
+        backoff.reset();
+        assert_eq!(MIN_BACKOFF, backoff.next());
+    }
+
+    #[tokio::test]
+    async fn test_pause_stops_on_shutdown() {
+        let (tx, rx) = watch::channel(false);
+        assert!(pause(Duration::from_millis(1), rx.clone()).await);
+
+        let stopping = tokio::spawn(pause(Duration::from_secs(60), rx));
+        tx.send(true).unwrap();
+        assert!(!stopping.await.unwrap());
+    }
+}
@@ -0,0 +1,466 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use regex::bytes::Regex;
+use rsa::RsaPublicKey;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use time::OffsetDateTime;
+
+/// Somewhere to find the DKIM public key for a selector and domain.
+pub trait KeyLookup {
+    /// The TXT record for a name such as `selector._domainkey.example.com`.
+    fn txt(&self, name: &str) -> Result<Option<String>, String>;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Canonicalization {
+    Simple,
+    Relaxed,
+}
+
+/// Verify the DKIM signatures on a message, returning the domains (`d=`) of the ones that pass.
+///
+/// Only `rsa-sha256` signatures are supported. Reasons why signatures failed are in `Err`,
+/// when none passed.
+pub fn verify(message: &[u8], keys: &dyn KeyLookup) -> Result<Vec<String>, String> {
+    let message = crlf(message);
+    let (header, body) = split(&message);
+    let fields = fields(header);
+
+    let mut passed = Vec::new();
+    let mut failures = Vec::new();
+
+    for (name, raw) in fields.iter() {
+        if !name.eq_ignore_ascii_case("DKIM-Signature") {
+            continue;
+        }
+        match verify_signature(raw, &fields, body, keys) {
+            Ok(domain) => passed.push(domain),
+            Err(reason) => failures.push(reason),
+        }
+    }
+
+    if !passed.is_empty() {
+        Ok(passed)
+    } else if failures.is_empty() {
+        Err(String::from("no DKIM signature"))
+    } else {
+        Err(failures.join("; "))
+    }
+}
+
+fn verify_signature(
+    signature_field: &[u8],
+    fields: &[(String, &[u8])],
+    body: &[u8],
+    keys: &dyn KeyLookup,
+) -> Result<String, String> {
+    let tags = tags(&unfold(&value(signature_field)));
+    let tag = |name: &str| {
+        tags.get(name)
+            .cloned()
+            .ok_or_else(|| format!("DKIM signature has no {}=", name))
+    };
+
+    let domain = tag("d")?.to_lowercase();
+    let selector = tag("s")?;
+    let fail = |reason: &str| format!("DKIM signature for {}: {}", domain, reason);
+
+    if tag("v")? != "1" {
+        return Err(fail("unknown version"));
+    }
+    if tag("a")?.to_lowercase() != "rsa-sha256" {
+        return Err(fail("unsupported algorithm"));
+    }
+    if let Some(expires) = tags.get("x").and_then(|x| x.parse::<i64>().ok())
+        && expires < OffsetDateTime::now_utc().unix_timestamp()
+    {
+        return Err(fail("expired"));
+    }
+
+    // RFC 6376 5.4: otherwise the signature says nothing about who the message is from
+    let signed_fields = tag("h")?;
+    if !signed_fields
+        .split(':')
+        .any(|name| name.trim().eq_ignore_ascii_case("from"))
+    {
+        return Err(fail("From is not signed"));
+    }
+
+    let (header_canon, body_canon) = canonicalizations(tags.get("c"))?;
+
+    // The body
+    let canonical_body = canonical_body(body, body_canon);
+    // A partial l= would let anything appended after the signed text through
+    if let Some(length) = tags.get("l") {
+        let length: usize = length.parse().map_err(|_| fail("bad l="))?;
+        if length != canonical_body.len() {
+            return Err(fail("l= does not cover the whole body"));
+        }
+    }
+    let body_hash = decode(&tag("bh")?).ok_or_else(|| fail("bad bh="))?;
+    if Sha256::digest(&canonical_body).as_slice() != body_hash.as_slice() {
+        return Err(fail("body has changed"));
+    }
+
+    // The signed header fields, each taken from the bottom up, then the signature itself without b=
+    let mut data = Vec::new();
+    let mut used = vec![false; fields.len()];
+    for name in signed_fields.split(':').map(str::trim) {
+        let found = fields
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(i, (n, _))| !used[*i] && n.eq_ignore_ascii_case(name));
+        if let Some((i, (_, raw))) = found {
+            used[i] = true;
+            data.extend(canonical_header(raw, header_canon));
+        }
+    }
+    let unsigned = without_signature(signature_field);
+    let mut own = canonical_header(&unsigned, header_canon);
+    own.truncate(own.len() - 2);
+    data.extend(own);
+
+    let key = public_key(keys, &selector, &domain).map_err(|reason| fail(&reason))?;
+    let signature = decode(&tag("b")?)
+        .and_then(|b| Signature::try_from(b.as_slice()).ok())
+        .ok_or_else(|| fail("bad b="))?;
+
+    VerifyingKey::<Sha256>::new(key)
+        .verify(&data, &signature)
+        .map_err(|_| fail("signature does not match"))?;
+
+    Ok(domain)
+}
+
+fn canonicalizations(c: Option<&String>) -> Result<(Canonicalization, Canonicalization), String> {
+    let parse = |s: &str| match s {
+        "simple" => Ok(Canonicalization::Simple),
+        "relaxed" => Ok(Canonicalization::Relaxed),
+        other => Err(format!("unknown canonicalization {}", other)),
+    };
+    match c.map(|c| c.to_lowercase()) {
+        None => Ok((Canonicalization::Simple, Canonicalization::Simple)),
+        Some(c) => match c.split_once('/') {
+            Some((header, body)) => Ok((parse(header)?, parse(body)?)),
+            None => Ok((parse(&c)?, Canonicalization::Simple)),
+        },
+    }
+}
+
+fn public_key(keys: &dyn KeyLookup, selector: &str, domain: &str) -> Result<RsaPublicKey, String> {
+    let name = format!("{}._domainkey.{}", selector, domain);
+    let record = keys
+        .txt(&name)?
+        .ok_or_else(|| format!("no key at {}", name))?;
+    let tags = tags(&record);
+
+    if tags
+        .get("k")
+        .is_some_and(|k| !k.eq_ignore_ascii_case("rsa"))
+    {
+        return Err(String::from("unsupported key type"));
+    }
+    let der = match tags.get("p") {
+        Some(p) if !p.is_empty() => decode(p).ok_or("bad key")?,
+        _ => return Err(String::from("key revoked")),
+    };
+    RsaPublicKey::from_public_key_der(&der)
+        .or_else(|_| RsaPublicKey::from_pkcs1_der(&der))
+        .map_err(|_| String::from("bad key"))
+}
+
+/// `tag=value` pairs, separated by semicolons.
+fn tags(text: &str) -> BTreeMap<String, String> {
+    text.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| {
+            let value: String = value.split_whitespace().collect();
+            (name.trim().to_lowercase(), value)
+        })
+        .collect()
+}
+
+fn decode(base64: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(base64).ok()
+}
+
+/// Messages read from files may have bare LF line endings, but DKIM signs CRLF.
+fn crlf(message: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(message.len());
+    let mut previous = 0;
+    for &b in message {
+        if b == b'\n' && previous != b'\r' {
+            result.push(b'\r');
+        }
+        result.push(b);
+        previous = b;
+    }
+    result
+}
+
+fn split(message: &[u8]) -> (&[u8], &[u8]) {
+    match message.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => (&message[..pos + 2], &message[pos + 4..]),
+        None => (message, &[]),
+    }
+}
+
+/// Header fields, with their names, and their raw text including continuation lines and the final CRLF.
+fn fields(header: &[u8]) -> Vec<(String, &[u8])> {
+    let mut fields: Vec<(String, &[u8])> = Vec::new();
+    let mut start = 0;
+    for (i, window) in header.windows(2).enumerate() {
+        let next = header.get(i + 2);
+        let field_ends = window == b"\r\n" && !matches!(next, Some(b' ') | Some(b'\t'));
+        if field_ends {
+            let raw = &header[start..i + 2];
+            let name = raw.split(|&b| b == b':').next().unwrap_or_default();
+            fields.push((String::from_utf8_lossy(name).trim().to_string(), raw));
+            start = i + 2;
+        }
+    }
+    fields
+}
+
+fn value(field: &[u8]) -> String {
+    let text = String::from_utf8_lossy(field);
+    text.split_once(':')
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_default()
+}
+
+fn unfold(text: &str) -> String {
+    text.replace("\r\n", "")
+}
+
+/// The DKIM-Signature field with an empty `b=` value, as it was when it was signed.
+fn without_signature(field: &[u8]) -> Vec<u8> {
+    let pattern = Regex::new(r"(^|;)(\s*b\s*=)[^;]*").unwrap();
+    let (name, value) = field.split_at(field.iter().position(|&b| b == b':').unwrap_or(0) + 1);
+    let mut result = name.to_vec();
+    result.extend(pattern.replace(value, &b"$1$2"[..]).iter());
+    if !result.ends_with(b"\r\n") {
+        result.extend(b"\r\n");
+    }
+    result
+}
+
+fn canonical_header(field: &[u8], canon: Canonicalization) -> Vec<u8> {
+    match canon {
+        Canonicalization::Simple => field.to_vec(),
+        Canonicalization::Relaxed => {
+            let (name, value) = match field.iter().position(|&b| b == b':') {
+                Some(pos) => (&field[..pos], &field[pos + 1..]),
+                None => (field, &b""[..]),
+            };
+            let name = String::from_utf8_lossy(name).trim().to_lowercase();
+            let value: Vec<u8> = value
+                .iter()
+                .copied()
+                .filter(|&b| b != b'\r' && b != b'\n')
+                .collect();
+            let mut result = name.into_bytes();
+            result.push(b':');
+            result.extend(collapse_whitespace(&value).trim_ascii());
+            result.extend(b"\r\n");
+            result
+        }
+    }
+}
+
+fn canonical_body(body: &[u8], canon: Canonicalization) -> Vec<u8> {
+    let mut lines: Vec<Vec<u8>> = body
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect();
+    // The split leaves an empty last "line" after the final CRLF
+    if body.ends_with(b"\n") {
+        lines.pop();
+    }
+
+    if canon == Canonicalization::Relaxed {
+        lines = lines
+            .iter()
+            .map(|line| collapse_whitespace(line).trim_ascii_end().to_vec())
+            .collect();
+    }
+
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut result = Vec::new();
+    for line in lines.iter() {
+        result.extend(line);
+        result.extend(b"\r\n");
+    }
+    if result.is_empty() && canon == Canonicalization::Simple {
+        result.extend(b"\r\n");
+    }
+    result
+}
+
+fn collapse_whitespace(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        let is_space = b == b' ' || b == b'\t';
+        if !is_space {
+            result.push(b);
+        } else if result.last() != Some(&b' ') {
+            result.push(b' ');
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dkim");
+
+    /// Keys from the fixture key file.
+    struct Keys;
+
+    impl KeyLookup for Keys {
+        fn txt(&self, name: &str) -> Result<Option<String>, String> {
+            let keys = fs::read_to_string(format!("{}/keys.txt", FIXTURES)).unwrap();
+            Ok(keys
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .find(|(n, _)| *n == name)
+                .map(|(_, record)| record.to_string()))
+        }
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(format!("{}/{}", FIXTURES, name)).unwrap()
+    }
+
+    fn replace(message: &[u8], from: &str, to: &str) -> Vec<u8> {
+        String::from_utf8(message.to_vec())
+            .unwrap()
+            .replace(from, to)
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_relaxed() {
+        assert_eq!(
+            Ok(vec![String::from("example.com")]),
+            verify(&fixture("relaxed.eml"), &Keys)
+        );
+    }
+
+    #[test]
+    fn test_simple() {
+        assert_eq!(
+            Ok(vec![String::from("example.com")]),
+            verify(&fixture("simple.eml"), &Keys)
+        );
+    }
+
+    #[test]
+    fn test_relaxed_survives_whitespace_changes() {
+        let message = replace(&fixture("relaxed.eml"), "Subject: Walk", "Subject:  Walk");
+        let message = replace(&message, "lovely walk.  \r\n", "lovely  walk.\r\n");
+        assert!(verify(&message, &Keys).is_ok());
+    }
+
+    #[test]
+    fn test_bare_line_feeds() {
+        let message = replace(&fixture("relaxed.eml"), "\r\n", "\n");
+        assert!(verify(&message, &Keys).is_ok());
+    }
+
+    #[test]
+    fn test_changed_body() {
+        let message = replace(&fixture("simple.eml"), "asleep", "awake");
+        assert_eq!(
+            Err(String::from(
+                "DKIM signature for example.com: body has changed"
+            )),
+            verify(&message, &Keys)
+        );
+    }
+
+    #[test]
+    fn test_body_length() {
+        assert_eq!(
+            Err(String::from(
+                "DKIM signature for example.com: l= does not cover the whole body"
+            )),
+            verify(&fixture("length.eml"), &Keys)
+        );
+        assert!(verify(&fixture("length-whole.eml"), &Keys).is_ok());
+    }
+
+    #[test]
+    fn test_unsigned_from() {
+        assert_eq!(
+            Err(String::from(
+                "DKIM signature for example.com: From is not signed"
+            )),
+            verify(&fixture("unsigned-from.eml"), &Keys)
+        );
+    }
+
+    #[test]
+    fn test_changed_header() {
+        let message = replace(
+            &fixture("relaxed.eml"),
+            "alice@example.com",
+            "mallory@example.com",
+        );
+        assert_eq!(
+            Err(String::from(
+                "DKIM signature for example.com: signature does not match"
+            )),
+            verify(&message, &Keys)
+        );
+    }
+
+    #[test]
+    fn test_unknown_selector() {
+        let message = replace(&fixture("relaxed.eml"), "s=sel2026", "s=other");
+        assert!(
+            verify(&message, &Keys)
+                .unwrap_err()
+                .contains("no key at other._domainkey")
+        );
+    }
+
+    #[test]
+    fn test_unsigned() {
+        assert_eq!(
+            Err(String::from("no DKIM signature")),
+            verify(b"From: alice@example.com\r\n\r\nHello\r\n", &Keys)
+        );
+    }
+
+    #[test]
+    fn test_canonical_body() {
+        let body = b"Hello  \t world \r\n\r\n\r\n";
+        assert_eq!(
+            b"Hello world\r\n".to_vec(),
+            canonical_body(body, Canonicalization::Relaxed)
+        );
+        assert_eq!(
+            b"Hello  \t world \r\n".to_vec(),
+            canonical_body(body, Canonicalization::Simple)
+        );
+        assert_eq!(
+            b"\r\n".to_vec(),
+            canonical_body(b"", Canonicalization::Simple)
+        );
+        assert_eq!(b"".to_vec(), canonical_body(b"", Canonicalization::Relaxed));
+    }
+}
@@ -0,0 +1,269 @@
+use hickory_proto::op::{Edns, Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use log::debug;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use super::mishaps::Mishap;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The first nameserver in /etc/resolv.conf.
+pub fn system_server() -> Result<SocketAddr, Mishap> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf")?;
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|ip| ip.trim().parse().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .ok_or_else(|| Mishap::Dns(String::from("no nameserver in /etc/resolv.conf")))
+}
+
+/// Look up the TXT records for `name`. Each record's strings are joined together.
+///
+/// The query goes over UDP, and again over TCP if the answer didn't fit in a datagram.
+pub fn txt(server: SocketAddr, name: &str) -> Result<Vec<String>, Mishap> {
+    debug!("Looking up TXT {} via {}", name, server);
+    let mut fqdn = Name::from_ascii(name).map_err(|_| Mishap::Dns(format!("bad name {}", name)))?;
+    fqdn.set_fqdn(true);
+    let query = Query::query(fqdn, RecordType::TXT);
+
+    let mut response = exchange(&query, |request| udp(server, request))?;
+    if response.truncated() {
+        debug!("Answer for {} was truncated, retrying over TCP", name);
+        response = exchange(&query, |request| tcp(server, request))?;
+    }
+    answers(response)
+}
+
+/// Send `query` with a random id, and check the response is for it: a spoofed response would
+/// have to guess the id as well as the question.
+fn exchange(
+    query: &Query,
+    send: impl FnOnce(&[u8]) -> Result<Vec<u8>, Mishap>,
+) -> Result<Message, Mishap> {
+    let id = rand::random();
+    let mut request = Message::new();
+    request
+        .set_id(id)
+        .set_recursion_desired(true)
+        .add_query(query.clone());
+    // EDNS, so longer keys aren't truncated
+    request.set_edns(Edns::new().set_max_payload(4096).to_owned());
+
+    let packet = request.to_vec().map_err(dns_error)?;
+    let response = Message::from_vec(&send(&packet)?).map_err(dns_error)?;
+
+    if response.message_type() != MessageType::Response
+        || response.id() != id
+        || response.queries() != [query.clone()]
+    {
+        return Err(Mishap::Dns(String::from("response for another query")));
+    }
+    Ok(response)
+}
+
+fn udp(server: SocketAddr, request: &[u8]) -> Result<Vec<u8>, Mishap> {
+    let socket = UdpSocket::bind(if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(request)?;
+
+    let mut buffer = [0; 4096];
+    let len = socket.recv(&mut buffer)?;
+    Ok(buffer[..len].to_vec())
+}
+
+/// Over TCP, each message is preceded by its length.
+fn tcp(server: SocketAddr, request: &[u8]) -> Result<Vec<u8>, Mishap> {
+    let mut stream = TcpStream::connect_timeout(&server, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let len =
+        u16::try_from(request.len()).map_err(|_| Mishap::Dns(String::from("query too long")))?;
+    stream.write_all(&[len.to_be_bytes().as_slice(), request].concat())?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+fn answers(response: Message) -> Result<Vec<String>, Mishap> {
+    match response.response_code() {
+        ResponseCode::NoError => {}
+        ResponseCode::NXDomain => return Ok(Vec::new()),
+        code => return Err(Mishap::Dns(format!("server replied with {}", code))),
+    }
+
+    Ok(response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            RData::TXT(txt) => Some(String::from_utf8_lossy(&txt.txt_data().concat()).to_string()),
+            _ => None,
+        })
+        .collect())
+}
+
+fn dns_error(err: hickory_proto::ProtoError) -> Mishap {
+    Mishap::Dns(err.to_string())
+}
+
+/// A DNS server answering TXT queries from a fixed list, for tests.
+#[cfg(test)]
+pub mod stub {
+    use super::*;
+    use hickory_proto::rr::Record;
+    use hickory_proto::rr::rdata::TXT;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Answers over UDP are limited to 512 bytes, as from a server without EDNS,
+    /// so longer ones are only given over TCP.
+    pub fn serve(records: Vec<(String, Vec<String>)>) -> SocketAddr {
+        respond(move |request| {
+            let mut reply = reply_to(request);
+            let name = request.queries()[0].name().to_ascii();
+            match records.iter().find(|(n, _)| format!("{}.", n) == name) {
+                Some((_, strings)) => {
+                    let txt = RData::TXT(TXT::new(strings.clone()));
+                    reply.add_answer(Record::from_rdata(
+                        Name::from_ascii(&name).unwrap(),
+                        60,
+                        txt,
+                    ));
+                }
+                None => {
+                    reply.set_response_code(ResponseCode::NXDomain);
+                }
+            }
+            reply
+        })
+    }
+
+    /// An empty response to `request`.
+    pub fn reply_to(request: &Message) -> Message {
+        let mut reply = Message::new();
+        reply
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_recursion_desired(true)
+            .set_recursion_available(true)
+            .add_queries(request.queries().to_vec());
+        reply
+    }
+
+    /// Answer each query, over UDP and TCP on the same port, with what `handler` returns.
+    pub fn respond(handler: impl Fn(&Message) -> Message + Send + Sync + 'static) -> SocketAddr {
+        let (socket, listener) = loop {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            if let Ok(listener) = TcpListener::bind(socket.local_addr().unwrap()) {
+                break (socket, listener);
+            }
+        };
+        let addr = socket.local_addr().unwrap();
+        let handler = Arc::new(handler);
+
+        let udp_handler = handler.clone();
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+                let request = Message::from_vec(&buffer[..len]).unwrap();
+                let mut reply = udp_handler(&request).to_vec().unwrap();
+                if reply.len() > 512 {
+                    let mut truncated = reply_to(&request);
+                    truncated.set_truncated(true);
+                    reply = truncated.to_vec().unwrap();
+                }
+                socket.send_to(&reply, from).unwrap();
+            }
+        });
+
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut request = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut request).unwrap();
+
+                let reply = handler(&Message::from_vec(&request).unwrap())
+                    .to_vec()
+                    .unwrap();
+                let len = (reply.len() as u16).to_be_bytes();
+                stream
+                    .write_all(&[len.as_slice(), &reply].concat())
+                    .unwrap();
+            }
+        });
+        addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_txt() {
+        let server = stub::serve(vec![(
+            String::from("sel._domainkey.example.com"),
+            vec![String::from("v=DKIM1; k=rsa; "), String::from("p=ABC")],
+        )]);
+        assert_eq!(
+            vec![String::from("v=DKIM1; k=rsa; p=ABC")],
+            txt(server, "sel._domainkey.example.com").unwrap()
+        );
+        assert!(txt(server, "missing.example.com").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_truncated_over_tcp() {
+        let key = format!("v=DKIM1; k=rsa; p={}", "A".repeat(600));
+        let strings: Vec<String> = key
+            .as_bytes()
+            .chunks(255)
+            .map(|c| String::from_utf8(c.to_vec()).unwrap())
+            .collect();
+        let server = stub::serve(vec![(String::from("sel._domainkey.example.com"), strings)]);
+        assert_eq!(
+            vec![key],
+            txt(server, "sel._domainkey.example.com").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_response_for_another_query() {
+        let wrong_id = stub::respond(|request| {
+            let mut reply = stub::reply_to(request);
+            reply.set_id(request.id().wrapping_add(1));
+            reply
+        });
+        let wrong_question = stub::respond(|request| {
+            let mut reply = stub::reply_to(request);
+            reply.take_queries();
+            reply.add_query(Query::query(
+                Name::from_ascii("sel._domainkey.example.net.").unwrap(),
+                RecordType::TXT,
+            ));
+            reply
+        });
+
+        for server in [wrong_id, wrong_question] {
+            assert_eq!(
+                "DNS lookup failed: response for another query",
+                txt(server, "sel._domainkey.example.com")
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
+    }
+}
@@ -1,107 +1,145 @@
 use log::debug;
 use mailparse::*;
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 use time::OffsetDateTime;
 
+use super::authenticity;
+use super::authors::Directory;
 use super::blog::{Attachment, PostInfo};
+use super::command::{self, Command};
+use super::embed;
 use super::filenames::Filenames;
+use super::flowed;
+use super::footer;
+use super::html;
 use super::media::RenameExt;
+use super::preamble::{self, Preamble};
+use super::quoteblock;
+use super::senders;
 use super::settings::Settings;
 use super::signatureblock;
+use super::signed;
+use super::subject;
 use super::tag::Tag;
+use super::token;
 
 use super::mishaps::Mishap;
 
-pub fn fetch(settings: &Settings) -> Result<Option<String>, Mishap> {
-    debug!("Fetching");
-    let client = imap::ClientBuilder::new(&settings.imap_hostname, settings.imap_port).connect()?;
-
-    let mut imap_session = client
-        .login(&settings.imap_user, &settings.imap_password)
-        .map_err(|(err, _client)| err)?;
-
-    debug!("Selecting mailbox: {}", &settings.mailbox);
-    imap_session.select(&settings.mailbox)?;
-
-    // fetch message number 1 in this mailbox
-    let messages = imap_session.fetch("1", "RFC822")?;
-    debug!("Messages: {:?}", messages.len());
-
-    let message = if let Some(m) = messages.iter().next() {
-        m
-    } else {
-        imap_session.logout()?;
-        return Ok(None);
-    };
-
-    // The body will be the mime content of the message (including heeader)
-    let body = message.body().expect("message did not have a body!");
-    let body = std::str::from_utf8(body)
-        .expect("message was not valid utf-8")
-        .to_string();
-
-    if settings.expunge {
-        imap_session.store("1", "+FLAGS (\\Seen \\Deleted)")?;
-        let _msg_sequence_numbers = imap_session.expunge()?;
+pub fn parse(mime_msg: &[u8]) -> Result<ParsedMail<'_>, Mishap> {
+    if mime_msg.iter().all(|b| b.is_ascii_whitespace()) {
+        return Err(Mishap::EmptyMessage);
     }
-
-    imap_session.logout()?;
-
-    Ok(Some(body))
+    let result = mailparse::parse_mail(mime_msg)?;
+    Ok(result)
 }
 
-pub fn parse(mime_msg: &str) -> Result<ParsedMail<'_>, Mishap> {
-    let bytes = mime_msg.as_bytes();
-    let result = mailparse::parse_mail(bytes)?;
-    Ok(result)
+/// The Message-ID header, if the message has one.
+pub fn message_id(mime_msg: &[u8]) -> Option<String> {
+    let (headers, _) = mailparse::parse_headers(mime_msg).ok()?;
+    headers
+        .get_first_value("Message-ID")
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
 }
 
 pub fn extract(
     settings: &Settings,
+    directory: &Directory,
     working_dir: &Path,
     mail: ParsedMail,
 ) -> Result<PostInfo, Mishap> {
-    validate_sender(settings, &mail).and_then(|_| read_post(settings, working_dir, mail))
+    validate_sender(settings, &mail).and_then(|_| read_post(settings, directory, working_dir, mail))
 }
 
 fn validate_sender(settings: &Settings, mail: &ParsedMail) -> Result<(), Mishap> {
-    let from_address: Option<String> = from(mail)?;
+    // DKIM checks the last From, but we'd read the first, so the two could be different senders
+    if mail.headers.get_all_headers("From").len() > 1 {
+        return Err(Mishap::EmailField(String::from("more than one From")));
+    }
 
-    if settings.allowed_domains.is_empty() {
-        Ok(())
-    } else {
-        match from_address {
-            None => Err(Mishap::MissingSender),
-            Some(email) => {
-                let matching_domain = settings
-                    .allowed_domains
-                    .iter()
-                    .find(|&d| email.ends_with(d));
-                if matching_domain.is_none() {
-                    Err(Mishap::Unauthorised(email))
-                } else {
-                    Ok(())
-                }
-            }
-        }
+    let anyone_allowed = settings.allowed_domains.is_empty()
+        && settings.allowed_addresses.is_empty()
+        && settings.denied_senders.is_empty()
+        && settings.trusted_authserv_id.is_empty()
+        && !settings.verify_dkim
+        && settings.pgp_keyring.is_none()
+        && settings.smime_certs.is_none();
+
+    match from(mail)? {
+        Some(address) => senders::check(settings, &address)
+            .and_then(|_| authenticity::check(settings, mail.raw_bytes, &address))
+            .and_then(|_| signed::check(settings, mail, &address)),
+        None if anyone_allowed => Ok(()),
+        None => Err(Mishap::MissingSender),
     }
 }
 
 fn read_post(
     settings: &Settings,
+    directory: &Directory,
     working_dir: &Path,
     mail: ParsedMail,
 ) -> Result<PostInfo, Mishap> {
-    let sender: String = sender_name(&mail)?.unwrap_or_else(|| String::from("Someone"));
-    let subject: Option<String> = mail.headers.get_first_value("Subject");
-    let content: Option<String> = body(&mail)?.map(signatureblock::remove);
-    let date: OffsetDateTime = date(&mail)?.unwrap_or_else(OffsetDateTime::now_utc);
+    let address = from(&mail)?;
+    let headers = signed::trusted_headers(settings, &mail);
+    let (subject, content) = token::check(
+        settings,
+        &mail,
+        address.as_deref(),
+        headers.get_first_value("Subject"),
+        body(&mail)?,
+    )?;
+
+    // Fields set at the start of the body take priority over the email headers
+    let (preamble, content) = match content {
+        None => (Preamble::default(), None),
+        Some(text) => {
+            let (preamble, text) = preamble::split(text, settings.reject_unknown_fields)?;
+            (preamble, Some(text))
+        }
+    };
+
+    // An author named in the body is taken as given, otherwise look up the sender
+    let profile = match (&preamble.author, &address) {
+        (None, Some(address)) => directory.find(address),
+        _ => None,
+    };
+    let sender: String = match (preamble.author, &profile) {
+        (Some(author), _) => author,
+        (None, Some(profile)) => profile.author.name.clone(),
+        (None, None) => sender_name(&mail)?.unwrap_or_else(|| String::from("Someone")),
+    };
+    let (subject, commands) = match subject {
+        None => (None, Vec::new()),
+        Some(subject) => {
+            let (subject, commands) = command::decommand(&subject)?;
+            (
+                Some(subject::normalise(&subject, &settings.subject_prefix)),
+                commands,
+            )
+        }
+    };
+    let content: Option<String> = content
+        .map(|text| quoteblock::remove(text, settings.keep_forwarded))
+        .map(|text| footer::remove(text, &settings.footer_pattern))
+        .map(|text| signatureblock::remove(text, settings.lenient_signature));
+    let backdate = commands.iter().find_map(|c| match c {
+        Command::Date(date) => Some(*date),
+        _ => None,
+    });
+    let date: OffsetDateTime = match preamble.date.or(backdate) {
+        Some(date) => date,
+        None => date(headers)?.unwrap_or_else(OffsetDateTime::now_utc),
+    };
 
     // The blog post title will be the subject line, and if that's missing use the body text
-    let raw_title = subject
+    let raw_title = preamble
+        .title
+        .or(subject)
         .filter(|str| !str.is_empty())
         .or_else(|| content.clone())
         .unwrap_or_else(|| String::from("Untitled"));
@@ -121,25 +159,61 @@ fn read_post(
     let attachments = attachments(&conventions, working_dir, &mail)?;
 
     // if there is a video attachment, add the video tag
-    let auto_tags = if attachments.iter().any(|a| a.is_video()) {
+    let mut auto_tags = if attachments.iter().any(|a| a.is_video()) {
         vec![Tag::new("video")]
     } else {
         vec![]
     };
 
-    Ok(PostInfo::new(
-        title,
-        sender,
-        content,
-        date,
-        vec![tags, auto_tags].into_iter().flatten().collect(),
-        attachments,
-        conventions.post_github_path(),
-    ))
+    // and likewise for embedded videos and posts
+    for tag in content.iter().flat_map(|text| embed::tags(text)) {
+        if !auto_tags.contains(&tag) {
+            auto_tags.push(tag);
+        }
+    }
+
+    // Fields set in the body win over subject commands, which win over the author's profile
+    let mut extra = profile.map(|p| p.fields()).unwrap_or_default();
+    extra.extend(command_fields(&commands));
+    extra.extend(preamble.extra);
+
+    Ok(PostInfo {
+        message_id: message_id(mail.raw_bytes),
+        summary: preamble.summary,
+        draft: preamble.draft.unwrap_or(commands.contains(&Command::Draft)),
+        feature_image: !commands.contains(&Command::NoFeature),
+        extra,
+        ..PostInfo::new(
+            title,
+            sender,
+            content,
+            date,
+            vec![tags, preamble.tags, auto_tags]
+                .into_iter()
+                .flatten()
+                .collect(),
+            attachments,
+            conventions.post_github_path(),
+        )
+    })
 }
 
-fn date(mail: &ParsedMail) -> Result<Option<OffsetDateTime>, Mishap> {
-    match mail.headers.get_first_value("Date") {
+/// Front matter for the `[private]` and `[pin]` subject commands.
+fn command_fields(commands: &[Command]) -> BTreeMap<String, serde_yaml::Value> {
+    let mut fields = BTreeMap::new();
+    if commands.contains(&Command::Private) {
+        let mut build = serde_yaml::Mapping::new();
+        build.insert("list".into(), "never".into());
+        fields.insert(String::from("build"), serde_yaml::Value::Mapping(build));
+    }
+    if commands.contains(&Command::Pin) {
+        fields.insert(String::from("pinned"), serde_yaml::Value::Bool(true));
+    }
+    fields
+}
+
+fn date(headers: &[MailHeader]) -> Result<Option<OffsetDateTime>, Mishap> {
+    match headers.get_first_value("Date") {
         None => Ok(None),
         Some(str) => date_parse(&str),
     }
@@ -174,14 +248,38 @@ fn from(mail: &ParsedMail) -> Result<Option<String>, MailParseError> {
     walk_from_header(mail, |info| Some(info.addr))
 }
 
+/// The text of the message, preferring a `text/plain` part anywhere in the message,
+/// and otherwise converting a `text/html` part to Markdown.
+///
+/// HTML that places inline images (`cid:` references) is preferred over plain text,
+/// which loses where the images go.
 fn body(mail: &ParsedMail) -> Result<Option<String>, MailParseError> {
-    if mail.ctype.mimetype == "text/plain" {
-        mail.get_body().map(Some)
+    let html = find_text(mail, mail, "text/html")?;
+    let html_places_images = html.as_ref().is_some_and(|html| html.contains("cid:"));
+
+    match find_text(mail, mail, "text/plain")? {
+        Some(plain) if !html_places_images => Ok(Some(plain)),
+        plain => Ok(html.map(|html| html::to_markdown(&html)).or(plain)),
+    }
+}
+
+fn find_text(
+    root: &ParsedMail,
+    mail: &ParsedMail,
+    mimetype: &str,
+) -> Result<Option<String>, MailParseError> {
+    if mail.ctype.mimetype == mimetype {
+        text(mail).map(Some)
     } else if mail.subparts.is_empty() {
         Ok(None)
+    } else if mimetype == "text/plain" && is_interleaved(mail) {
+        interleaved_text(root, mail).map(Some)
     } else {
-        let parts: Result<Vec<Option<String>>, MailParseError> =
-            mail.subparts.iter().map(body).collect();
+        let parts: Result<Vec<Option<String>>, MailParseError> = mail
+            .subparts
+            .iter()
+            .map(|part| find_text(root, part, mimetype))
+            .collect();
 
         let valid_parts: Result<Vec<String>, MailParseError> =
             parts.map(|os| os.into_iter().flatten().collect());
@@ -194,6 +292,87 @@ fn body(mail: &ParsedMail) -> Result<Option<String>, MailParseError> {
     }
 }
 
+/// Apple Mail sends plain text with inline images as a multipart/mixed of text, image, text, ...
+fn is_interleaved(mail: &ParsedMail) -> bool {
+    let inline_text_parts = mail
+        .subparts
+        .iter()
+        .filter(|part| part.ctype.mimetype == "text/plain" && is_inline(part))
+        .count();
+
+    mail.ctype.mimetype == "multipart/mixed" && inline_text_parts > 1
+}
+
+fn is_inline(part: &ParsedMail) -> bool {
+    part.get_content_disposition().disposition == DispositionType::Inline
+}
+
+/// Join the text parts, with a `cid:` reference where each inline image or video sits.
+fn interleaved_text(root: &ParsedMail, mail: &ParsedMail) -> Result<String, MailParseError> {
+    let attachments = find_attachments(root);
+    let mut joined = String::new();
+
+    for part in mail.subparts.iter() {
+        if part.ctype.mimetype == "text/plain" && is_inline(part) {
+            joined.push_str(&text(part)?);
+        } else if let Some(index) = attachments.iter().position(|a| std::ptr::eq(*a, part))
+            && is_inline(part)
+        {
+            joined.push_str(&format!("\n\n![](cid:{})\n\n", content_id(part, index)));
+        }
+    }
+
+    Ok(joined)
+}
+
+/// The Content-ID of a part, or one made up from its position for parts without one.
+fn content_id(part: &ParsedMail, index: usize) -> String {
+    part.headers
+        .get_first_value("Content-ID")
+        .map(|id| {
+            id.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| format!("dogpost-part-{}", index))
+}
+
+/// Decode a text part, unwrapping it if it is `format=flowed`.
+fn text(part: &ParsedMail) -> Result<String, MailParseError> {
+    let decoded = decode(part)?;
+
+    let param_is = |name: &str, value: &str| {
+        part.ctype
+            .params
+            .get(name)
+            .is_some_and(|v| v.eq_ignore_ascii_case(value))
+    };
+
+    if part.ctype.mimetype == "text/plain" && param_is("format", "flowed") {
+        Ok(flowed::unwrap(&decoded, param_is("delsp", "yes")))
+    } else {
+        Ok(decoded)
+    }
+}
+
+/// Decode a text part using its declared charset.
+///
+/// With no charset declared (which mailparse reports as "us-ascii"), 8-bit text is most likely UTF-8,
+/// or failing that, Latin-1.
+fn decode(part: &ParsedMail) -> Result<String, MailParseError> {
+    if part.ctype.charset.eq_ignore_ascii_case("us-ascii") {
+        let bytes = part.get_body_raw()?;
+        match String::from_utf8(bytes) {
+            Ok(str) => Ok(str),
+            Err(err) => Ok(err.as_bytes().iter().map(|&b| b as char).collect()),
+        }
+    } else {
+        part.get_body()
+    }
+}
+
 fn to_vec<T>(o: Option<T>) -> Vec<T> {
     match o {
         Some(v) => vec![v],
@@ -281,7 +460,12 @@ fn find_attachments<'a>(mail: &'a ParsedMail<'a>) -> Vec<&'a ParsedMail<'a>> {
 
     let head: Vec<&ParsedMail> = to_vec(Some(mail).filter(|m| m.is_image() || m.is_video()));
 
-    let tail = mail.subparts.iter().flat_map(find_attachments);
+    // The second part of a signed message is the signature, not something to post
+    let parts = match mail.ctype.mimetype.as_str() {
+        "multipart/signed" => &mail.subparts[..mail.subparts.len().min(1)],
+        _ => &mail.subparts[..],
+    };
+    let tail = parts.iter().flat_map(find_attachments);
 
     head.into_iter().chain(tail).collect()
 }
@@ -305,6 +489,7 @@ fn attachments(
             url_path: conventions.attachment_markdown_url(count, &ext),
             github_path: conventions.attachment_github_path(count, &ext),
             mime_type: part.mime(),
+            content_id: content_id(part, count),
         };
 
         log::debug!("Found attachment: {:?}", img);
@@ -320,3 +505,166 @@ fn save_raw_body(filename: &Path, bytes: Vec<u8>) -> Result<File, Mishap> {
     file.write_all(bytes.as_slice())?;
     Ok(file)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_declared_latin1_body() {
+        let mime = b"Subject: Caf\xe9\r\nContent-Type: text/plain; charset=iso-8859-1\r\nContent-Transfer-Encoding: 8bit\r\n\r\nR\xe9sum\xe9 of the walk\r\n";
+        let mail = parse(mime).unwrap();
+        assert_eq!(
+            Some("Caf\u{e9}".to_string()),
+            mail.headers.get_first_value("Subject")
+        );
+        assert_eq!(
+            Some("R\u{e9}sum\u{e9} of the walk\r\n".to_string()),
+            body(&mail).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_undeclared_8bit_utf8_body() {
+        let mime = "Subject: Walk\r\nContent-Transfer-Encoding: 8bit\r\n\r\nRex \u{1f436} was na\u{ef}ve\r\n";
+        let mail = parse(mime.as_bytes()).unwrap();
+        assert_eq!(
+            Some("Rex \u{1f436} was na\u{ef}ve\r\n".to_string()),
+            body(&mail).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_undeclared_8bit_latin1_body() {
+        let mime = b"Subject: Walk\r\n\r\nna\xefve\r\n";
+        let mail = parse(mime).unwrap();
+        assert_eq!(Some("na\u{ef}ve\r\n".to_string()), body(&mail).unwrap());
+    }
+
+    #[test]
+    fn test_binary_junk_does_not_panic() {
+        let mime = b"\xff\xfe\x00\x01\x02 not really a message \x80\x81";
+        assert!(parse(mime).is_ok());
+    }
+
+    #[test]
+    fn test_message_id() {
+        let mime = b"Subject: Walk\r\nMessage-ID:  <walk-1@example.com>\r\n\r\nHello\r\n";
+        assert_eq!(Some("<walk-1@example.com>".to_string()), message_id(mime));
+        assert_eq!(None, message_id(b"Subject: Walk\r\n\r\nHello\r\n"));
+    }
+
+    #[test]
+    fn test_sender_required_by_rules() {
+        let mail = parse(b"Subject: Walk\r\n\r\nHello").unwrap();
+        let settings = Settings::for_tests(&[]);
+        assert!(validate_sender(&settings, &mail).is_ok());
+
+        let settings = Settings::for_tests(&["--denied-senders", "spam.example.org"]);
+        assert!(matches!(
+            validate_sender(&settings, &mail),
+            Err(Mishap::MissingSender)
+        ));
+    }
+
+    #[test]
+    fn test_several_from_headers() {
+        let mail = parse(
+            b"From: alice@example.com\r\nFrom: mallory@example.net\r\nSubject: Walk\r\n\r\nHello",
+        )
+        .unwrap();
+        let settings = Settings::for_tests(&[]);
+        assert_eq!(
+            "Bad email field: more than one From",
+            validate_sender(&settings, &mail).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_empty_message() {
+        assert!(matches!(parse(b" \r\n"), Err(Mishap::EmptyMessage)));
+    }
+
+    #[test]
+    fn test_prefers_plain_alternative() {
+        let mime = b"Subject: Walk\r\nContent-Type: multipart/alternative; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nPlain walk\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>HTML <b>walk</b></p>\r\n--b--\r\n";
+        let mail = parse(mime).unwrap();
+        assert_eq!(Some("Plain walk".to_string()), body(&mail).unwrap());
+    }
+
+    #[test]
+    fn test_html_only_body() {
+        let mime = b"Subject: Walk\r\nContent-Type: text/html; charset=utf-8\r\n\r\n<html><body><p>HTML <b>walk</b></p></body></html>\r\n";
+        let mail = parse(mime).unwrap();
+        assert_eq!(Some("HTML **walk**".to_string()), body(&mail).unwrap());
+    }
+
+    #[test]
+    fn test_prefers_html_placing_images() {
+        let mime = b"Subject: Beach\r\nContent-Type: multipart/alternative; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nRex at the beach\r\n--b\r\nContent-Type: multipart/related; boundary=r\r\n\r\n--r\r\nContent-Type: text/html\r\n\r\n<p>Rex at the beach</p><img src=\"cid:rex@example.com\"><p>asleep</p>\r\n--r\r\nContent-Type: image/jpeg\r\nContent-ID: <rex@example.com>\r\nContent-Disposition: inline\r\n\r\nJPEG\r\n--r--\r\n--b--\r\n";
+        let mail = parse(mime).unwrap();
+        assert_eq!(
+            Some("Rex at the beach\n\n![](cid:rex@example.com)\n\nasleep".to_string()),
+            body(&mail).unwrap()
+        );
+        assert_eq!("rex@example.com", content_id(find_attachments(&mail)[0], 0));
+    }
+
+    #[test]
+    fn test_interleaved_plain_text_and_images() {
+        let mime = b"Subject: Beach\r\nContent-Type: multipart/mixed; boundary=m\r\n\r\n--m\r\nContent-Type: text/plain\r\n\r\nRex at the beach\r\n--m\r\nContent-Type: image/jpeg; name=a.jpg\r\nContent-Disposition: inline; filename=a.jpg\r\n\r\nJPEG\r\n--m\r\nContent-Type: text/plain\r\n\r\nand asleep\r\n--m--\r\n";
+        let mail = parse(mime).unwrap();
+        assert_eq!(
+            Some("Rex at the beach\n\n![](cid:dogpost-part-0)\n\nand asleep".to_string()),
+            body(&mail).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_flowed_body() {
+        let mime = b"Subject: Walk\r\nContent-Type: text/plain; charset=utf-8; format=flowed; delsp=yes\r\n\r\nWe walked round the reser \r\nvoir.\r\n-- \r\nJane\r\n";
+        let mail = parse(mime).unwrap();
+        assert_eq!(
+            Some("We walked round the reservoir.\n-- \nJane\n".to_string()),
+            body(&mail).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_signature_is_not_an_attachment() {
+        let eml = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/signed/pgp.eml");
+        let bytes = std::fs::read(eml).unwrap();
+        let mail = parse(&bytes).unwrap();
+        let found: Vec<String> = find_attachments(&mail).iter().map(|p| p.mime()).collect();
+        assert_eq!(vec![String::from("image/png")], found);
+        assert_eq!(
+            Some("Rex had a lovely walk on the sand.".to_string()),
+            body(&mail).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_signed_subject_and_date_only_from_protected_headers() {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/signed/");
+        let settings =
+            Settings::for_tests(&["--pgp-keyring", &format!("{}pgp-keyring.asc", fixtures)]);
+        let working_dir = tempfile::tempdir().unwrap();
+        let extract_fixture = |name: &str| {
+            let bytes = std::fs::read(format!("{}{}", fixtures, name)).unwrap();
+            let mail = parse(&bytes).unwrap();
+            extract(&settings, &Directory::default(), working_dir.path(), mail).unwrap()
+        };
+
+        // The unsigned Subject and Date (with its commands) are ignored
+        let info = extract_fixture("pgp-protected.eml");
+        assert_eq!("At the beach", info.title);
+        assert!(!info.draft);
+        assert_eq!(2026, info.date.year());
+
+        // Without protected headers, the title comes from the body and the date is today
+        let info = extract_fixture("pgp.eml");
+        assert_eq!("Rex had a lovely walk on the sand.", info.title);
+        assert!(info.date > OffsetDateTime::now_utc() - time::Duration::minutes(1));
+    }
+}
@@ -0,0 +1,225 @@
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+use super::tag::Tag;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Provider {
+    YouTube,
+    Vimeo,
+    Instagram,
+}
+
+/// A link to a video or post that Hugo has a shortcode for.
+#[derive(PartialEq, Debug)]
+pub struct Embed {
+    pub provider: Provider,
+    pub id: String,
+}
+
+impl Embed {
+    fn shortcode(&self) -> String {
+        let name = match self.provider {
+            Provider::YouTube => "youtube",
+            Provider::Vimeo => "vimeo",
+            Provider::Instagram => "instagram",
+        };
+        format!("{{{{< {} {} >}}}}", name, self.id)
+    }
+
+    /// The tag added to posts with this kind of embed.
+    pub fn tag(&self) -> Tag {
+        match self.provider {
+            Provider::YouTube | Provider::Vimeo => Tag::new("video"),
+            Provider::Instagram => Tag::new("instagram"),
+        }
+    }
+}
+
+/// Links to each provider, capturing the video or post id.
+static PROVIDERS: LazyLock<Vec<(Provider, Regex)>> = LazyLock::new(|| {
+    [
+        (
+            Provider::YouTube,
+            r"^https?://(?:www\.|m\.)?(?:youtube\.com/(?:watch\?(?:.*&)?v=|shorts/|embed/|live/)|youtu\.be/)([\w-]{11})(?:[?&#/].*)?$",
+        ),
+        (
+            Provider::Vimeo,
+            r"^https?://(?:www\.)?(?:vimeo\.com/|player\.vimeo\.com/video/)(\d+)(?:[?#/].*)?$",
+        ),
+        (
+            Provider::Instagram,
+            r"^https?://(?:www\.)?instagram\.com/(?:p|reel|tv)/([\w-]+)/?(?:[?#].*)?$",
+        ),
+    ]
+    .into_iter()
+    .map(|(provider, pattern)| (provider, Regex::new(pattern).unwrap()))
+    .collect()
+});
+
+/// Not already in a Markdown link, "[text](url)", or an autolink, "<url>"
+static BARE_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(^\(?|\s\(?|[^\]\s(]\()(https?://[^\s<>]+)").unwrap());
+
+/// The embed for a line that is nothing but a link to a known provider.
+pub fn find(line: &str) -> Option<Embed> {
+    let url = line.trim().trim_start_matches('<').trim_end_matches('>');
+
+    PROVIDERS.iter().find_map(|(provider, pattern)| {
+        let caps = pattern.captures(url)?;
+        Some(Embed {
+            provider: *provider,
+            id: caps[1].to_string(),
+        })
+    })
+}
+
+/// Tags for the embeds in some text, without repeats. Links in code blocks aren't embedded, so don't count.
+pub fn tags(text: &str) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let embeds = lines(text)
+        .into_iter()
+        .filter(|(_, in_code)| !in_code)
+        .filter_map(|(line, _)| find(line));
+    for tag in embeds.map(|e| e.tag()) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// Replace links to known providers, on lines of their own, with Hugo shortcodes,
+/// and turn other bare URLs into Markdown autolinks. Code blocks are left alone.
+pub fn link(text: &str) -> String {
+    let lines: Vec<String> = lines(text)
+        .into_iter()
+        .map(|(line, in_code)| {
+            if in_code {
+                line.to_string()
+            } else if let Some(embed) = find(line) {
+                embed.shortcode()
+            } else {
+                BARE_URL
+                    .replace_all(line, |caps: &Captures| autolink(&caps[1], &caps[2]))
+                    .to_string()
+            }
+        })
+        .collect();
+
+    lines.join("\n")
+}
+
+/// Each line of the text, and whether it's code: in a fenced block, or indented by four spaces.
+fn lines(text: &str) -> Vec<(&str, bool)> {
+    let mut in_fence = false;
+    text.lines()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+            }
+            (line, in_fence || line.starts_with("    "))
+        })
+        .collect()
+}
+
+/// Punctuation ending a sentence isn't part of the link, nor is a closing bracket without an opening one.
+fn autolink(before: &str, url: &str) -> String {
+    let mut end = url.len();
+    loop {
+        let trimmed = &url[..end];
+        let unbalanced =
+            trimmed.ends_with(')') && trimmed.matches('(').count() < trimmed.matches(')').count();
+        if trimmed.ends_with(['.', ',', ';', ':', '!', '?', '"', '\'']) || unbalanced {
+            end -= 1;
+        } else {
+            break;
+        }
+    }
+    format!("{}<{}>{}", before, &url[..end], &url[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn embed(provider: Provider, id: &str) -> Option<Embed> {
+        Some(Embed {
+            provider,
+            id: id.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_find_youtube() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?feature=share&v=dQw4w9WgXcQ&t=10",
+            "https://youtu.be/dQw4w9WgXcQ?si=abc",
+            "https://m.youtube.com/shorts/dQw4w9WgXcQ",
+            "  <https://www.youtube.com/embed/dQw4w9WgXcQ>  ",
+        ] {
+            assert_eq!(
+                embed(Provider::YouTube, "dQw4w9WgXcQ"),
+                find(url),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_vimeo_and_instagram() {
+        assert_eq!(
+            embed(Provider::Vimeo, "76979871"),
+            find("https://vimeo.com/76979871")
+        );
+        assert_eq!(
+            embed(Provider::Vimeo, "76979871"),
+            find("https://player.vimeo.com/video/76979871?h=1")
+        );
+        assert_eq!(
+            embed(Provider::Instagram, "CxYz_12-ab"),
+            find("https://www.instagram.com/p/CxYz_12-ab/?igsh=xyz")
+        );
+        assert_eq!(
+            embed(Provider::Instagram, "Cabc123"),
+            find("https://instagram.com/reel/Cabc123")
+        );
+    }
+
+    #[test]
+    fn test_find_only_whole_lines() {
+        assert_eq!(None, find("Watch https://youtu.be/dQw4w9WgXcQ"));
+        assert_eq!(None, find("https://example.com/watch?v=dQw4w9WgXcQ"));
+        assert_eq!(None, find("https://vimeo.com/channels/staffpicks"));
+    }
+
+    #[test]
+    fn test_link() {
+        let text = "Rex on the beach:\n\nhttps://youtu.be/dQw4w9WgXcQ\n\nMore at https://example.com/rex_photos. Or (https://example.com/a).\n[album](https://example.com/album) <https://example.com/b>";
+        assert_eq!(
+            "Rex on the beach:\n\n{{< youtube dQw4w9WgXcQ >}}\n\nMore at <https://example.com/rex_photos>. Or (<https://example.com/a>).\n[album](https://example.com/album) <https://example.com/b>",
+            link(text)
+        );
+    }
+
+    #[test]
+    fn test_link_leaves_code_alone() {
+        let text = "```\nhttps://example.com\n```";
+        assert_eq!(text, link(text));
+    }
+
+    #[test]
+    fn test_tags() {
+        let text = "https://youtu.be/dQw4w9WgXcQ\nhttps://vimeo.com/76979871\nhttps://instagram.com/p/Cabc123/";
+        assert_eq!(vec![Tag::new("video"), Tag::new("instagram")], tags(text));
+    }
+
+    #[test]
+    fn test_no_tags_from_code() {
+        let text = "```\nhttps://youtu.be/dQw4w9WgXcQ\n```\n    https://instagram.com/p/Cabc123/";
+        assert!(tags(text).is_empty());
+    }
+}
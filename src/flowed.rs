@@ -0,0 +1,126 @@
+/// Unwrap a `format=flowed` plain text body (RFC 3676) into one line per paragraph.
+///
+/// A line ending in a space is soft-wrapped and joins the next line, unless the quote depth changes.
+/// With `DelSp=yes` that trailing space was added by the sender, and is removed when joining.
+/// Quoted lines come out with one `>` per level, followed by a space.
+pub fn unwrap(text: &str, delsp: bool) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    // The paragraph being joined, and its quote depth
+    let mut paragraph: Option<(usize, String)> = None;
+
+    for raw_line in text.lines() {
+        let (depth, line) = unquote(raw_line);
+        let is_signature = line == "-- ";
+
+        // A change of quote depth, or a signature, ends the paragraph
+        if let Some((para_depth, _)) = &paragraph
+            && (*para_depth != depth || is_signature)
+            && let Some((para_depth, para)) = paragraph.take()
+        {
+            lines.push(quote(para_depth, &para));
+        }
+
+        let is_flowed = line.ends_with(' ') && !is_signature;
+        let line = if is_flowed && delsp {
+            &line[..line.len() - 1]
+        } else {
+            line
+        };
+
+        let joined = match paragraph.take() {
+            Some((_, mut para)) => {
+                para.push_str(line);
+                para
+            }
+            None => line.to_string(),
+        };
+
+        if is_flowed {
+            paragraph = Some((depth, joined));
+        } else {
+            lines.push(quote(depth, &joined));
+        }
+    }
+
+    if let Some((depth, para)) = paragraph {
+        lines.push(quote(depth, &para));
+    }
+
+    let mut unwrapped = lines.join("\n");
+    if text.ends_with('\n') {
+        unwrapped.push('\n');
+    }
+    unwrapped
+}
+
+/// Split a line into its quote depth and its content, undoing any space-stuffing.
+fn unquote(line: &str) -> (usize, &str) {
+    let content = line.trim_start_matches('>');
+    let depth = line.len() - content.len();
+    let content = content.strip_prefix(' ').unwrap_or(content);
+    (depth, content)
+}
+
+fn quote(depth: usize, line: &str) -> String {
+    if depth == 0 {
+        line.to_string()
+    } else {
+        format!("{} {}", ">".repeat(depth), line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signatureblock;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_joins_soft_wrapped_lines() {
+        let input = "We walked Rex round the \r\nreservoir this morning.\r\n\r\nHe was tired.\r\n";
+        assert_eq!(
+            "We walked Rex round the reservoir this morning.\n\nHe was tired.\n",
+            unwrap(input, false)
+        );
+    }
+
+    #[test]
+    fn test_delsp() {
+        let input = "Donaudampfschiff \r\nfahrt\r\n";
+        assert_eq!("Donaudampfschifffahrt\n", unwrap(input, true));
+        assert_eq!("Donaudampfschiff fahrt\n", unwrap(input, false));
+    }
+
+    #[test]
+    fn test_space_stuffing() {
+        let input = " From the beach\r\n >not a quote\r\n";
+        assert_eq!("From the beach\n>not a quote\n", unwrap(input, false));
+    }
+
+    #[test]
+    fn test_quote_levels() {
+        let input =
+            "Yes! \r\nSaturday.\r\n> Can you bring \r\n> him on \r\n>> Rex is \r\n>> lovely\r\n";
+        assert_eq!(
+            "Yes! Saturday.\n> Can you bring him on \n>> Rex is lovely\n",
+            unwrap(input, false)
+        );
+    }
+
+    #[test]
+    fn test_hard_line_breaks_kept() {
+        let input = "Shopping:\r\nBall\r\nLead\r\n";
+        assert_eq!("Shopping:\nBall\nLead\n", unwrap(input, false));
+    }
+
+    #[test]
+    fn test_signature_still_removed() {
+        let input = "Rex had a \r\nlovely day \r\n-- \r\nJane \r\nSmith\r\n";
+        let unwrapped = unwrap(input, false);
+        assert_eq!("Rex had a lovely day \n-- \nJane Smith\n", unwrapped);
+        assert_eq!(
+            "Rex had a lovely day \n",
+            signatureblock::remove(unwrapped, false)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use regex::{Regex, RegexBuilder};
+use std::sync::LazyLock;
+
+/// Footers added by mail apps and carriers, matched against whole lines, ignoring case.
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    r"Sent (from|via|using|with) (my |the )?[\w .,'&-]*(iPhone|iPad|iPod|Android|BlackBerry|Galaxy|smartphone|phone|tablet|mobile|device)[\w .,'&-]*",
+    r"Sent from (Outlook|Mail|Yahoo Mail|AOL Mobile Mail|Gmail Mobile|Samsung Mail)( for [\w ]+| on [\w ]+)?\.?",
+    r"Get (Outlook|Yahoo Mail|the Yahoo Mail app|BlueMail) for (iOS|Android|iPhone)\.?",
+    r"Sent with (Proton ?Mail|BlackBerry Work|Spark)[\w .]*",
+    r"(Envoyé de mon|Von meinem|Enviado desde mi|Enviado do meu|Inviato da|Verzonden met mijn|Skickat från min) [\w ]+",
+];
+
+static DEFAULT_FOOTERS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    DEFAULT_PATTERNS
+        .iter()
+        .map(|pattern| footer_line(pattern).unwrap())
+        .collect()
+});
+
+/// Remove app and carrier footers, like "Sent from my iPhone", from the end of a message.
+///
+/// `extra_patterns` are added to the defaults, and come from `footer_line`.
+pub fn remove(str: String, extra_patterns: &[Regex]) -> String {
+    let is_footer = |line: &str| {
+        DEFAULT_FOOTERS
+            .iter()
+            .chain(extra_patterns)
+            .any(|p| p.is_match(line))
+    };
+
+    let mut lines: Vec<&str> = str.lines().collect();
+    while let Some(last) = lines.last()
+        && (last.trim().is_empty() || is_footer(last.trim()))
+    {
+        lines.pop();
+    }
+
+    let mut cleaned = lines.join("\n");
+    if str.ends_with('\n') && !cleaned.is_empty() {
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+/// A footer is the whole line, perhaps followed by a link (as Outlook adds).
+///
+/// Used to check the patterns given in the settings when they are parsed.
+pub fn footer_line(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&format!(r"^(?:{})\s*(<[^>]*>)?$", pattern))
+        .case_insensitive(true)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn remove_defaults(input: &str) -> String {
+        remove(input.to_string(), &[])
+    }
+
+    #[test]
+    fn test_without_footer() {
+        let input = "Rex had a lovely walk.\n";
+        assert_eq!(input, remove_defaults(input));
+    }
+
+    #[test]
+    fn test_default_footers() {
+        let footers = [
+            "Sent from my iPhone",
+            "Sent from my iPad",
+            "Sent from my Samsung Galaxy smartphone.",
+            "Sent from my Verizon, Samsung Galaxy smartphone",
+            "Sent from my T-Mobile 4G LTE Device",
+            "Sent via the Samsung Galaxy S23 Ultra, an AT&T 5G smartphone",
+            "Get Outlook for Android",
+            "Get Outlook for iOS<https://aka.ms/o0ukef>",
+            "Sent from Outlook for iOS",
+            "Sent from Mail for Windows",
+            "Sent from Yahoo Mail on Android",
+            "Sent with Proton Mail secure email.",
+            "Von meinem iPhone gesendet",
+            "Envoyé de mon iPhone",
+        ];
+        for footer in footers {
+            let input = format!("Rex had a lovely walk.\n\n{}\n", footer);
+            assert_eq!(
+                "Rex had a lovely walk.\n",
+                remove_defaults(&input),
+                "{}",
+                footer
+            );
+        }
+    }
+
+    #[test]
+    fn test_several_footers() {
+        let input = "Rex had a lovely walk.\n\nSent from my iPhone\n\nGet Outlook for iOS\n\n";
+        assert_eq!("Rex had a lovely walk.\n", remove_defaults(input));
+    }
+
+    #[test]
+    fn test_only_at_the_end() {
+        let input = "Sent from my iPhone\nwhile walking Rex.\n";
+        assert_eq!(input, remove_defaults(input));
+    }
+
+    #[test]
+    fn test_ordinary_last_lines_kept() {
+        let input = "We sent a postcard.\nSent from the beach, with love\n";
+        assert_eq!(input, remove_defaults(input));
+    }
+
+    #[test]
+    fn test_extra_patterns() {
+        let input = "Rex had a lovely walk.\n-- \nFree texts with Acme Mobile!\n";
+        let extra = vec![footer_line(r"Free texts with .*!").unwrap()];
+        assert_eq!(
+            "Rex had a lovely walk.\n-- \n",
+            remove(input.to_string(), &extra)
+        );
+    }
+
+    #[test]
+    fn test_bad_pattern() {
+        assert!(footer_line("Sent from (").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use ego_tree::NodeRef;
+use scraper::{Html, Node};
+
+/// Stands in for a leading space that must survive tidying (list indentation), until the very end.
+const INDENT: char = '\u{1}';
+
+/// Ends the current line, unless we're already at the start of one, as block elements like `<div>` do.
+const BLOCK: char = '\u{2}';
+
+/// Convert the HTML body of an email into Markdown.
+///
+/// Paragraphs, emphasis, links, lists, headings and quotes are kept.
+/// Styles, scripts and tracking pixels are dropped, as is any other markup.
+pub fn to_markdown(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let markdown = children(*document.root_element());
+
+    let lines: Vec<String> = tidy(&markdown)
+        .lines()
+        .map(|line| line.replace(INDENT, " "))
+        .collect();
+
+    lines.join("\n")
+}
+
+fn children(node: NodeRef<Node>) -> String {
+    node.children().map(render).collect()
+}
+
+fn render(node: NodeRef<Node>) -> String {
+    match node.value() {
+        Node::Text(text) => escape(&collapse_whitespace(text)),
+        Node::Element(element) => match element.name() {
+            "head" | "style" | "script" | "title" | "meta" | "link" | "template" => String::new(),
+
+            "br" => String::from("\n"),
+            "hr" => String::from("\n\n* * *\n\n"),
+
+            "p" | "blockquote" if is_empty(node) => String::new(),
+            "p" => format!("\n\n{}\n\n", tidy(&children(node))),
+            "div" | "section" | "article" | "header" | "footer" | "main" | "center" | "tr"
+            | "table" | "tbody" | "thead" | "dl" | "dt" | "dd" => {
+                format!("{}{}{}", BLOCK, children(node), BLOCK)
+            }
+            "td" | "th" => format!("{} ", children(node)),
+
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = element.name()[1..].parse().unwrap_or(1);
+                let text = tidy(&children(node)).replace('\n', " ");
+                format!("\n\n{} {}\n\n", "#".repeat(level), text)
+            }
+
+            "blockquote" => {
+                let quoted: Vec<String> = tidy(&children(node))
+                    .lines()
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect();
+                format!("\n\n{}\n\n", quoted.join("\n"))
+            }
+
+            "ul" | "ol" => list(node, element.name() == "ol", element.attr("start")),
+
+            "pre" => format!("\n\n```\n{}\n```\n\n", preformatted(&raw_text(node))),
+
+            "strong" | "b" => emphasis(node, "**"),
+            "em" | "i" => emphasis(node, "*"),
+            "code" | "tt" => emphasis_raw(&raw_text(node), "`"),
+
+            "a" => link(node, element.attr("href")),
+
+            "img" => image(element),
+
+            _ => children(node),
+        },
+        _ => String::new(),
+    }
+}
+
+fn list(node: NodeRef<Node>, ordered: bool, start: Option<&str>) -> String {
+    let mut number: usize = start.and_then(|s| s.parse().ok()).unwrap_or(1);
+    let mut items = Vec::new();
+
+    for item in node.children() {
+        let is_item = item
+            .value()
+            .as_element()
+            .map(|e| e.name() == "li")
+            .unwrap_or(false);
+        if !is_item {
+            continue;
+        }
+
+        let marker = if ordered {
+            format!("{}. ", number)
+        } else {
+            String::from("- ")
+        };
+        number += 1;
+
+        // Continuation lines line up with the text after the marker
+        let indent: String = std::iter::repeat_n(INDENT, marker.len()).collect();
+        // Items are kept tight, with no blank lines, so a nested list stays part of its item
+        let content = tidy(&children(item));
+        let lines: Vec<String> = content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(i, line)| match i {
+                0 => format!("{}{}", marker, line),
+                _ => format!("{}{}", indent, line),
+            })
+            .collect();
+        items.push(lines.join("\n"));
+    }
+
+    format!("\n\n{}\n\n", items.join("\n"))
+}
+
+fn emphasis(node: NodeRef<Node>, marker: &str) -> String {
+    emphasis_raw(&children(node), marker)
+}
+
+/// Wrap text in a marker, keeping surrounding whitespace outside, where Markdown needs it.
+fn emphasis_raw(text: &str, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let leading = if text.starts_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    let trailing = if text.ends_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    format!("{}{}{}{}{}", leading, marker, trimmed, marker, trailing)
+}
+
+fn link(node: NodeRef<Node>, href: Option<&str>) -> String {
+    let text = children(node);
+    let label = text.trim();
+
+    match href.map(str::trim) {
+        None => text,
+        Some(href) if href.is_empty() || href.starts_with('#') => text,
+        Some(_) if label.is_empty() => text,
+        Some(href) if href.strip_prefix("mailto:") == Some(label) => text,
+        Some(href) if unescape(label) == href => format!("<{}>", href),
+        Some(href) => format!("[{}]({})", label, href.replace(' ', "%20")),
+    }
+}
+
+fn image(element: &scraper::node::Element) -> String {
+    let src = element.attr("src").unwrap_or("").trim();
+    let alt = element.attr("alt").unwrap_or("").trim();
+
+    if is_tracking_pixel(element) || src.is_empty() || src.starts_with("data:") {
+        String::new()
+    } else {
+        format!("![{}]({})", escape(alt), src.replace(' ', "%20"))
+    }
+}
+
+/// Invisible or 1x1 images are there to report that the email has been opened.
+fn is_tracking_pixel(element: &scraper::node::Element) -> bool {
+    let tiny = |attr: &str| {
+        element
+            .attr(attr)
+            .map(|v| v.trim().trim_end_matches("px"))
+            .map(|v| v == "0" || v == "1")
+            .unwrap_or(false)
+    };
+
+    let style: String = element
+        .attr("style")
+        .unwrap_or("")
+        .to_lowercase()
+        .split_whitespace()
+        .collect();
+
+    tiny("width")
+        || tiny("height")
+        || style.contains("display:none")
+        || style.contains("visibility:hidden")
+        || style.contains("width:1px")
+        || style.contains("height:1px")
+}
+
+fn is_empty(node: NodeRef<Node>) -> bool {
+    tidy(&children(node)).is_empty()
+}
+
+/// Text as-is, for preformatted content.
+fn raw_text(node: NodeRef<Node>) -> String {
+    node.descendants()
+        .filter_map(|n| n.value().as_text().map(|t| t.to_string()))
+        .collect()
+}
+
+/// Keep the indentation of preformatted lines through tidying.
+fn preformatted(text: &str) -> String {
+    let lines: Vec<String> = text
+        .trim_end()
+        .lines()
+        .map(|line| {
+            let content = line.trim_start_matches(' ');
+            let indent = line.len() - content.len();
+            format!("{}{}", INDENT.to_string().repeat(indent), content)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// HTML treats any run of whitespace as a single space.
+fn collapse_whitespace(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_space {
+                result.push(' ');
+            }
+            in_space = true;
+        } else {
+            result.push(c);
+            in_space = false;
+        }
+    }
+    result
+}
+
+/// Trailing whitespace is insignificant in HTML, so a `--` line is taken as the `-- ` signature delimiter.
+fn signature_delimiter(line: &str) -> &str {
+    if line == "--" { "-- " } else { line }
+}
+
+fn break_blocks(markdown: &str) -> String {
+    let mut result = String::with_capacity(markdown.len());
+    let mut at_line_start = true;
+    for c in markdown.chars() {
+        match c {
+            BLOCK if at_line_start => {}
+            BLOCK | '\n' => {
+                result.push('\n');
+                at_line_start = true;
+            }
+            c => {
+                result.push(c);
+                at_line_start = at_line_start && c.is_whitespace();
+            }
+        }
+    }
+    result
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+fn unescape(text: &str) -> String {
+    text.replace('\\', "")
+}
+
+/// Break lines at block boundaries, trim each line, and collapse runs of blank lines into one.
+fn tidy(markdown: &str) -> String {
+    let markdown = break_blocks(markdown);
+    let mut lines: Vec<&str> = Vec::new();
+    for line in markdown.lines().map(str::trim).map(signature_delimiter) {
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::path::Path;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/html");
+
+    /// Each `.html` email body in the fixtures is converted and compared to its `.md` file.
+    #[test]
+    fn test_golden_files() {
+        let mut count = 0;
+        for entry in fs::read_dir(FIXTURES).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|e| e.to_str()) != Some("html") {
+                continue;
+            }
+
+            let html = fs::read_to_string(&path).unwrap();
+            let expected = fs::read_to_string(path.with_extension("md")).unwrap();
+            assert_eq!(
+                expected.trim_end(),
+                to_markdown(&html),
+                "{}",
+                Path::new(&path).display()
+            );
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn test_emphasis_and_links() {
+        assert_eq!(
+            "A **bold** and *italic* [walk](https://example.com/walk)",
+            to_markdown(
+                r#"A <b>bold </b>and <i>italic</i> <a href="https://example.com/walk">walk</a>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_bare_link() {
+        assert_eq!(
+            "<https://example.com/a_b>",
+            to_markdown(r#"<a href="https://example.com/a_b">https://example.com/a_b</a>"#)
+        );
+    }
+
+    #[test]
+    fn test_nested_lists() {
+        assert_eq!(
+            "- Walk\n  1. Park\n  2. Beach\n- Dinner",
+            to_markdown(
+                "<ul><li>Walk<ol><li>Park</li><li>Beach</li></ol></li><li>Dinner</li></ul>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_drops_tracking_pixels_and_styles() {
+        assert_eq!(
+            "Hello",
+            to_markdown(
+                r#"<style>p { color: red; }</style><p>Hello</p><img src="https://t.example.com/open.gif" width="1" height="1">"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_escapes_markdown() {
+        assert_eq!(
+            r"2 \* 3 \[not a link\]",
+            to_markdown("<p>2 * 3 [not a link]</p>")
+        );
+    }
+}
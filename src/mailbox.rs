@@ -0,0 +1,226 @@
+use imap::extensions::idle;
+use imap::{Connection, Session};
+use log::{debug, warn};
+use std::time::Duration;
+
+use super::auth::{Credentials, OAuth2};
+use super::connection;
+use super::mishaps::Mishap;
+use super::settings::Settings;
+use super::sources::{Message, Origin, Source};
+use super::state::State;
+
+/// An open IMAP session on the mailbox we're reading posts from.
+pub struct Mailbox {
+    session: Session<Connection>,
+    mailbox_name: String,
+    expunge: bool,
+    archive_mailbox: Option<String>,
+    failed_mailbox: Option<String>,
+    supports_idle: bool,
+    supports_move: bool,
+    supports_uidplus: bool,
+}
+
+impl Mailbox {
+    pub fn open(settings: &Settings, credentials: &Credentials) -> Result<Mailbox, Mishap> {
+        let imap_user = settings
+            .imap_user
+            .as_deref()
+            .ok_or_else(|| Mishap::MissingSetting(String::from("--imap-user")))?;
+
+        let client = connection::connect(
+            &settings.imap_hostname,
+            settings.imap_port,
+            settings.imap_security,
+            &settings.ca_cert,
+        )?;
+
+        let mut session = match credentials {
+            Credentials::Password(password) => client
+                .login(imap_user, password)
+                .map_err(|(err, _client)| err)?,
+            Credentials::OAuth {
+                method,
+                access_token,
+            } => {
+                let authenticator = OAuth2 {
+                    method: *method,
+                    user: imap_user,
+                    access_token,
+                };
+                client
+                    .authenticate(authenticator.mechanism(), &authenticator)
+                    .map_err(|(err, _client)| err)?
+            }
+        };
+
+        let capabilities = session.capabilities()?;
+        let supports_idle = capabilities.has_str("IDLE");
+        let supports_move = capabilities.has_str("MOVE");
+        let supports_uidplus = capabilities.has_str("UIDPLUS");
+        debug!(
+            "Server supports IDLE: {}, MOVE: {}, UIDPLUS: {}",
+            supports_idle, supports_move, supports_uidplus
+        );
+
+        // A plain EXPUNGE would also remove messages other clients have marked as deleted
+        if settings.expunge && !supports_uidplus {
+            return Err(Mishap::MissingCapability(String::from(
+                "UIDPLUS, needed by --expunge (use --archive-mailbox instead)",
+            )));
+        }
+
+        Ok(Mailbox {
+            session,
+            mailbox_name: settings.mailbox.clone(),
+            expunge: settings.expunge,
+            archive_mailbox: settings.archive_mailbox.clone(),
+            failed_mailbox: settings.failed_mailbox.clone(),
+            supports_idle,
+            supports_move,
+            supports_uidplus,
+        })
+    }
+
+    fn move_to(&mut self, uid: &str, destination: &str) -> Result<(), Mishap> {
+        debug!("Moving {} to {}", uid, destination);
+        if self.supports_move {
+            self.session.uid_mv(uid, destination)?;
+            Ok(())
+        } else {
+            self.session.uid_copy(uid, destination)?;
+            self.delete(uid)
+        }
+    }
+
+    /// Mark the message as deleted, and expunge it if the server lets us expunge just this one.
+    /// Otherwise it is left for the user's mail client to expunge.
+    fn delete(&mut self, uid: &str) -> Result<(), Mishap> {
+        self.session.uid_store(uid, "+FLAGS (\\Seen \\Deleted)")?;
+        if self.supports_uidplus {
+            let _msg_sequence_numbers = self.session.uid_expunge(uid)?;
+        }
+        Ok(())
+    }
+
+    /// Block until the mailbox changes, or `poll_interval` has passed.
+    /// Servers without IDLE support are simply polled.
+    pub fn wait(&mut self, poll_interval: Duration) -> Result<(), Mishap> {
+        if self.supports_idle {
+            debug!("Waiting via IDLE");
+            let _outcome = self
+                .session
+                .idle()
+                .timeout(poll_interval)
+                .keepalive(false)
+                .wait_while(idle::stop_on_any)?;
+        } else {
+            debug!("Waiting via polling");
+            std::thread::sleep(poll_interval);
+        }
+        Ok(())
+    }
+}
+
+impl Source for Mailbox {
+    /// Fetch messages with a UID above the last one recorded in `state`, plus any that failed
+    /// before and are still in the mailbox, oldest first.
+    fn fetch(&mut self, state: &mut State, limit: Option<u32>) -> Result<Vec<Message>, Mishap> {
+        debug!("Selecting mailbox: {}", &self.mailbox_name);
+        let mailbox = self.session.select(&self.mailbox_name)?;
+        state.validate(mailbox.uid_validity);
+
+        if mailbox.exists == 0 {
+            return Ok(Vec::new());
+        }
+
+        // NB: "N:*" always matches the last message, even if its UID is below N
+        let mut uids: Vec<u32> = self
+            .session
+            .uid_search(format!("UID {}:* UNDELETED", state.last_uid + 1))?
+            .into_iter()
+            .filter(|&uid| uid > state.last_uid)
+            .collect();
+
+        if !state.failed_uids.is_empty() {
+            let failed: Vec<String> = state
+                .failed_uids
+                .iter()
+                .map(|uid| uid.to_string())
+                .collect();
+            let remaining = self
+                .session
+                .uid_search(format!("UID {} UNDELETED", failed.join(",")))?;
+            // Forget failures that have since been moved or deleted
+            state.failed_uids.retain(|uid| remaining.contains(uid));
+            uids.extend(remaining);
+        }
+        uids.sort_unstable();
+        uids.dedup();
+
+        if let Some(limit) = limit {
+            uids.truncate(limit as usize);
+        }
+
+        debug!("New message UIDs: {:?}", uids);
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let uid_set: Vec<String> = uids.iter().map(|uid| uid.to_string()).collect();
+        let fetches = self.session.uid_fetch(uid_set.join(","), "(UID RFC822)")?;
+
+        let mut messages = Vec::new();
+        for fetch in fetches.iter() {
+            // Without a UID we couldn't record the message, so leave it for the next fetch
+            let Some(uid) = fetch.uid else {
+                warn!(
+                    "Skipping message {}: server did not return a UID",
+                    fetch.message
+                );
+                continue;
+            };
+            // An empty message is rejected when parsed, rather than stopping the whole fetch
+            let mime = fetch.body().map(|body| body.to_vec()).unwrap_or_default();
+            messages.push(Message {
+                origin: Origin::Imap(uid),
+                mime,
+            });
+        }
+        messages.sort_by_key(|m| match m.origin {
+            Origin::Imap(uid) => uid,
+            _ => 0,
+        });
+
+        Ok(messages)
+    }
+
+    /// Called once a message has been processed.
+    ///
+    /// A successfully posted message is moved to the archive mailbox, or deleted if we're expunging.
+    /// A failed message is moved to the failed mailbox, or otherwise left where it is.
+    fn done(&mut self, message: &Message, posted: bool) -> Result<(), Mishap> {
+        let uid = match message.origin {
+            Origin::Imap(uid) => uid.to_string(),
+            _ => return Ok(()),
+        };
+
+        let destination = if posted {
+            self.archive_mailbox.clone()
+        } else {
+            self.failed_mailbox.clone()
+        };
+
+        match destination {
+            Some(destination) => self.move_to(&uid, &destination),
+            None if posted && self.expunge => self.delete(&uid),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), Mishap> {
+        self.session.logout()?;
+        Ok(())
+    }
+}
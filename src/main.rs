@@ -1,20 +1,46 @@
+use authors::Directory;
 use clap::Parser;
-use github::{Github, NewContent};
+use github::Github;
 use log::info;
+use mailbox::Mailbox;
 use mishaps::Mishap;
-use tempfile::TempDir;
+use sources::Source;
+use state::State;
 
 mod settings;
 use settings::Settings;
+mod auth;
+mod authenticity;
+mod authors;
+mod batch;
 mod blog;
+mod command;
+mod connection;
+mod daemon;
+mod dkim;
+mod dns;
 mod email;
+mod embed;
 mod filenames;
+mod flowed;
+mod footer;
 mod github;
+mod html;
 mod image;
+mod mailbox;
 mod media;
 mod mishaps;
+mod pop3;
+mod preamble;
+mod quoteblock;
+mod senders;
 mod signatureblock;
+mod signed;
+mod sources;
+mod state;
+mod subject;
 mod tag;
+mod token;
 mod video;
 
 #[tokio::main]
@@ -24,7 +50,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     ensure_imagemagick_installed();
     ensure_ffmpeg_installed();
 
-    let working_dir = TempDir::new().expect("creating temporary directory");
     let settings = Settings::parse();
 
     let gh = Github::new(
@@ -33,42 +58,54 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         &settings.github_branch,
     );
 
-    let extract = |msg| email::extract(&settings, working_dir.path(), msg);
-
-    match email::fetch(&settings) {
-        Err(err) => stop("mailbox access", err), // Failed accessing mail box
-        Ok(None) => complete(0),                 // No messages to process
-        Ok(Some(mime_message)) => {
-            match email::parse(&mime_message)
-                .and_then(extract)
-                .and_then(media::transcode)
-            {
-                Err(err) => stop("msg parse", err), // Message processing failed
-                Ok(info) => match blog::write(&info) {
-                    Err(err) => stop("Blog write", err),
-                    Ok(markdown) => {
-                        let commit_msg = format!("add post: {}", info.title);
-
-                        let mut contents: Vec<NewContent> = info
-                            .attachments
-                            .iter()
-                            .map(|a| NewContent::path(&a.github_path, &a.file_path))
-                            .collect();
-                        contents.push(NewContent::text(&info.file_path, &markdown));
-
-                        if settings.dry_run {
-                            dbg!(&info);
-                            dbg!(&contents);
-                        } else {
-                            gh.commit(&commit_msg, &contents).await?;
-                        }
-                    }
-                },
-            }
-        }
+    let directory = match &settings.authors_file {
+        Some(path) => Directory::load(path).unwrap_or_else(|err| stop("authors file", err)),
+        None => Directory::default(),
+    };
+
+    let mut state = if settings.reset_state {
+        State::default()
+    } else {
+        State::load(&settings.state_file).unwrap_or_else(|err| stop("state file", err))
+    };
+
+    if settings.daemon {
+        daemon::run(&settings, &gh, &directory, state).await;
+        complete(0)
     }
 
-    Ok(())
+    let selected_source =
+        sources::from_settings(&settings).unwrap_or_else(|err| stop("message source", err));
+
+    let mut source: Box<dyn Source> = match selected_source {
+        Some(source) => source,
+        None => {
+            let credentials = auth::credentials(&settings)
+                .await
+                .unwrap_or_else(|err| stop("authentication", err));
+
+            let mailbox = Mailbox::open(&settings, &credentials)
+                .unwrap_or_else(|err| stop("mailbox access", err));
+
+            Box::new(mailbox)
+        }
+    };
+
+    let outcomes = batch::process(&settings, &gh, &directory, source.as_mut(), &mut state)
+        .await
+        .unwrap_or_else(|err| stop("message source", err));
+
+    source
+        .close()
+        .unwrap_or_else(|err| stop("message source", err));
+
+    batch::summarise(&outcomes);
+
+    if outcomes.iter().any(|o| o.is_err()) {
+        std::process::exit(1)
+    } else {
+        complete(outcomes.len())
+    }
 }
 
 fn stop(context: &str, err: Mishap) -> ! {
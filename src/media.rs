@@ -19,6 +19,7 @@ fn normalize_filenames(a: Attachment) -> Result<Attachment, Mishap> {
         url_path: norm(a.url_path),
         github_path: norm(a.github_path),
         mime_type: a.mime_type,
+        content_id: a.content_id,
     })
 }
 
@@ -42,6 +43,7 @@ fn transcode_heic(a: Attachment) -> Result<Attachment, Mishap> {
             url_path: a.url_path.with_extension(target_ext),
             github_path: a.github_path.with_extension(target_ext),
             mime_type: target_mime_type.to_string(),
+            content_id: a.content_id,
         })
     }
 }
@@ -65,6 +67,7 @@ fn transcode_video_for_web(a: Attachment) -> Result<Attachment, Mishap> {
             url_path: a.url_path.with_extension(target_ext),
             github_path: a.github_path.with_extension(target_ext),
             mime_type: target_mime_type.to_string(),
+            content_id: a.content_id,
         })
     }
 }
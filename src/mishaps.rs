@@ -5,24 +5,66 @@ pub enum Mishap {
     #[error(transparent)]
     Imap(#[from] imap::error::Error),
 
+    #[error("POP3 server replied: {0}")]
+    Pop3(String),
+
+    #[error("Unencrypted connections are only allowed to localhost, not {0}")]
+    Insecure(String),
+
+    #[error("IMAP server does not support {0}")]
+    MissingCapability(String),
+
+    #[error("Bad certificate file: {0}")]
+    Certificate(String),
+
+    #[error("OAuth: {0}")]
+    OAuth(String),
+
+    #[error("Missing setting: {0}")]
+    MissingSetting(String),
+
     #[error(transparent)]
     Email(#[from] mailparse::MailParseError),
 
+    #[error("Message was empty")]
+    EmptyMessage,
+
     #[error("Bad email field: {0}")]
     EmailField(String),
 
+    #[error("Bad front matter in message body: {0}")]
+    Preamble(String),
+
+    #[error("Bad authors file: {0}")]
+    Authors(String),
+
     #[error(transparent)]
     DateOutOfRange(#[from] time::error::ComponentRange),
 
     #[error(transparent)]
     PostEncoding(#[from] std::string::FromUtf8Error),
 
+    #[error(transparent)]
+    State(#[from] serde_json::Error),
+
     #[error(transparent)]
     File(#[from] std::io::Error),
 
+    #[error("Commit to GitHub failed: {0}")]
+    Commit(String),
+
     #[error("No FROM address found")]
     MissingSender,
 
-    #[error("Sender {0} not in allowed list of domains")]
-    Unauthorised(String),
+    #[error("Sender {sender} not allowed: {rule}")]
+    Unauthorised { sender: String, rule: String },
+
+    #[error("Sender {sender} could not be verified: {reason}")]
+    Unverified { sender: String, reason: String },
+
+    #[error("Message has no valid posting token")]
+    MissingToken,
+
+    #[error("DNS lookup failed: {0}")]
+    Dns(String),
 }
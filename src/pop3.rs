@@ -0,0 +1,325 @@
+use log::debug;
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use super::connection::{self, Security};
+use super::mishaps::Mishap;
+use super::settings::Settings;
+use super::sources::{Message, Origin, Source};
+use super::state::State;
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+/// A POP3 (RFC 1939) mailbox. Messages already seen are recognised by their UIDL.
+pub struct Pop3 {
+    stream: BufReader<Box<dyn Stream>>,
+    expunge: bool,
+}
+
+impl Pop3 {
+    pub fn open(settings: &Settings, hostname: &str) -> Result<Pop3, Mishap> {
+        let security = settings.pop3_security;
+        connection::ensure_secure(hostname, security)?;
+
+        debug!(
+            "Connecting to {}:{} using {:?}",
+            hostname, settings.pop3_port, security
+        );
+        let tcp = TcpStream::connect((hostname, settings.pop3_port))?;
+
+        let mut pop3 = match security {
+            Security::Tls => {
+                let tls = connection::tls(hostname, tcp, &settings.ca_cert)?;
+                Pop3::new(Box::new(tls), settings.expunge)
+            }
+            Security::Plain => Pop3::new(Box::new(tcp), settings.expunge),
+            Security::Starttls => {
+                let mut plain = Pop3::new(Box::new(tcp.try_clone()?), settings.expunge);
+                plain.response()?;
+                plain.command("STLS")?;
+                let tls = connection::tls(hostname, tcp, &settings.ca_cert)?;
+                Pop3::new(Box::new(tls), settings.expunge)
+            }
+        };
+
+        if security != Security::Starttls {
+            let greeting = pop3.response()?;
+            debug!("Greeting: {}", greeting);
+        }
+
+        let user = settings
+            .pop3_user
+            .as_deref()
+            .ok_or_else(|| Mishap::MissingSetting(String::from("--pop3-user")))?;
+        let password = settings
+            .pop3_password
+            .as_deref()
+            .ok_or_else(|| Mishap::MissingSetting(String::from("--pop3-password")))?;
+
+        pop3.command(&format!("USER {}", user))?;
+        pop3.command(&format!("PASS {}", password))?;
+
+        Ok(pop3)
+    }
+
+    fn new(stream: Box<dyn Stream>, expunge: bool) -> Pop3 {
+        Pop3 {
+            stream: BufReader::new(stream),
+            expunge,
+        }
+    }
+
+    /// Read a single line response, failing on "-ERR".
+    fn response(&mut self) -> Result<String, Mishap> {
+        let line = self.line()?;
+        if line.starts_with("+OK") {
+            Ok(line)
+        } else {
+            Err(Mishap::Pop3(line))
+        }
+    }
+
+    fn line(&mut self) -> Result<String, Mishap> {
+        let mut buf = Vec::new();
+        if self.stream.read_until(b'\n', &mut buf)? == 0 {
+            return Err(Mishap::Pop3(String::from("connection closed")));
+        }
+        Ok(String::from_utf8_lossy(&buf).trim_end().to_string())
+    }
+
+    fn command(&mut self, command: &str) -> Result<String, Mishap> {
+        let logged = if command.starts_with("PASS ") {
+            "PASS ****"
+        } else {
+            command
+        };
+        debug!("POP3: {}", logged);
+
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.response()
+    }
+
+    /// Send a command with a multi-line response, returning the lines with dot-stuffing removed.
+    fn multi_line(&mut self, command: &str) -> Result<Vec<Vec<u8>>, Mishap> {
+        self.command(command)?;
+
+        let mut lines = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            if self.stream.read_until(b'\n', &mut buf)? == 0 {
+                return Err(Mishap::Pop3(String::from("connection closed")));
+            }
+            let line = buf
+                .strip_suffix(b"\r\n")
+                .or_else(|| buf.strip_suffix(b"\n"))
+                .unwrap_or(&buf);
+
+            if line == b"." {
+                return Ok(lines);
+            }
+            lines.push(line.strip_prefix(b".").unwrap_or(line).to_vec());
+        }
+    }
+
+    /// Message numbers and their unique IDs.
+    fn uidl(&mut self) -> Result<Vec<(u32, String)>, Mishap> {
+        let mut listing = Vec::new();
+        for line in self.multi_line("UIDL")? {
+            let line = String::from_utf8_lossy(&line);
+            if let Some((number, uidl)) = line.trim().split_once(' ')
+                && let Ok(number) = number.parse()
+            {
+                listing.push((number, uidl.to_string()));
+            }
+        }
+        Ok(listing)
+    }
+}
+
+impl Source for Pop3 {
+    fn fetch(&mut self, state: &mut State, limit: Option<u32>) -> Result<Vec<Message>, Mishap> {
+        let listing = self.uidl()?;
+
+        // Forget UIDLs for messages no longer on the server
+        let current: BTreeSet<&String> = listing.iter().map(|(_, uidl)| uidl).collect();
+        state.pop3_uidls.retain(|uidl| current.contains(uidl));
+
+        let mut new: Vec<(u32, String)> = listing
+            .into_iter()
+            .filter(|(_, uidl)| !state.pop3_uidls.contains(uidl))
+            .collect();
+        new.sort_unstable();
+
+        if let Some(limit) = limit {
+            new.truncate(limit as usize);
+        }
+
+        let mut messages = Vec::new();
+        for (number, uidl) in new {
+            let lines = self.multi_line(&format!("RETR {}", number))?;
+            let mut mime = Vec::new();
+            for line in lines {
+                mime.extend_from_slice(&line);
+                mime.extend_from_slice(b"\r\n");
+            }
+            messages.push(Message {
+                origin: Origin::Pop3 { number, uidl },
+                mime,
+            });
+        }
+
+        Ok(messages)
+    }
+
+    fn done(&mut self, message: &Message, posted: bool) -> Result<(), Mishap> {
+        match &message.origin {
+            Origin::Pop3 { number, .. } if posted && self.expunge => {
+                self.command(&format!("DELE {}", number))?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletions only take effect when we QUIT.
+    fn close(&mut self) -> Result<(), Mishap> {
+        self.command("QUIT")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use pretty_assertions::assert_eq;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// A POP3 server holding two messages, recording the commands it receives.
+    fn pop3_stub() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = commands.clone();
+
+        std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(tcp.try_clone().unwrap());
+            let mut writer = tcp;
+            writer.write_all(b"+OK POP3 stub ready\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let command = line.trim_end().to_string();
+                received.lock().unwrap().push(command.clone());
+
+                let reply: &[u8] = match command.as_str() {
+                    "UIDL" => b"+OK\r\n1 aaa\r\n2 bbb\r\n.\r\n",
+                    "RETR 1" => b"+OK\r\nSubject: One\r\n\r\nFirst\r\n.\r\n",
+                    "RETR 2" => b"+OK\r\nSubject: Two\r\n\r\n..Second\r\n.\r\n",
+                    "QUIT" => {
+                        writer.write_all(b"+OK bye\r\n").unwrap();
+                        break;
+                    }
+                    _ => b"+OK\r\n",
+                };
+                writer.write_all(reply).unwrap();
+            }
+        });
+
+        (port, commands)
+    }
+
+    fn settings(port: u16, expunge: bool) -> Settings {
+        let mut args = vec![
+            "dogpost".to_string(),
+            "--pop3-hostname=127.0.0.1".to_string(),
+            format!("--pop3-port={}", port),
+            "--pop3-security=plain".to_string(),
+            "--pop3-user=dog".to_string(),
+            "--pop3-password=woof".to_string(),
+            "--github-token=gh".to_string(),
+            "--github-repo=user/repo".to_string(),
+        ];
+        if expunge {
+            args.push("--expunge".to_string());
+        }
+        Settings::parse_from(args)
+    }
+
+    #[test]
+    fn test_fetch_and_delete() {
+        let (port, commands) = pop3_stub();
+        let mut state = State::default();
+
+        let mut pop3 = Pop3::open(&settings(port, true), "127.0.0.1").unwrap();
+        let messages = pop3.fetch(&mut state, None).unwrap();
+
+        assert_eq!(2, messages.len());
+        assert_eq!(b"Subject: One\r\n\r\nFirst\r\n".to_vec(), messages[0].mime);
+        assert_eq!(
+            b"Subject: Two\r\n\r\n.Second\r\n".to_vec(),
+            messages[1].mime
+        );
+
+        pop3.done(&messages[0], true).unwrap();
+        pop3.done(&messages[1], false).unwrap();
+        pop3.close().unwrap();
+
+        let commands = commands.lock().unwrap();
+        assert_eq!(
+            vec![
+                "USER dog",
+                "PASS woof",
+                "UIDL",
+                "RETR 1",
+                "RETR 2",
+                "DELE 1",
+                "QUIT"
+            ],
+            *commands
+        );
+    }
+
+    #[test]
+    fn test_skips_seen_uidls() {
+        let (port, _commands) = pop3_stub();
+        let mut state = State::default();
+        state.record(
+            &Origin::Pop3 {
+                number: 1,
+                uidl: String::from("aaa"),
+            },
+            true,
+        );
+        state.record(
+            &Origin::Pop3 {
+                number: 9,
+                uidl: String::from("gone"),
+            },
+            true,
+        );
+
+        let mut pop3 = Pop3::open(&settings(port, false), "127.0.0.1").unwrap();
+        let messages = pop3.fetch(&mut state, None).unwrap();
+
+        assert_eq!(1, messages.len());
+        assert_eq!(
+            Origin::Pop3 {
+                number: 2,
+                uidl: String::from("bbb")
+            },
+            messages[0].origin
+        );
+        assert!(!state.pop3_uidls.contains("gone"));
+    }
+}
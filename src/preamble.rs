@@ -0,0 +1,292 @@
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::macros::format_description;
+use time::{Date, OffsetDateTime, PrimitiveDateTime};
+
+use super::mishaps::Mishap;
+use super::tag::Tag;
+
+/// The fields that can be set from the start of the email body.
+pub const FIELDS: &[&str] = &["title", "date", "summary", "draft", "author", "tags"];
+
+/// Front matter fields that dogpost works out for itself, which can't be set as extras.
+const RESERVED: &[&str] = &["image", "type", "count", "message_id"];
+
+static KEY_VALUE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([A-Za-z][\w-]*)\s*:\s*(.*?)\s*$").unwrap());
+
+/// Post settings given at the start of the email body.
+#[derive(Debug, Default, PartialEq)]
+pub struct Preamble {
+    pub title: Option<String>,
+    pub date: Option<OffsetDateTime>,
+    pub summary: Option<String>,
+    pub draft: Option<bool>,
+    pub author: Option<String>,
+    pub tags: Vec<Tag>,
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// Separate any preamble from the rest of the body.
+///
+/// The preamble is either a YAML block between `---` lines, or leading `key: value` lines
+/// (including at least one of the `FIELDS`) followed by a blank line.
+/// Lines starting with `From:` are the headers of a forwarded message, not a preamble.
+/// Other keys are kept as `extra` front matter, unless `reject_unknown`.
+pub fn split(body: String, reject_unknown: bool) -> Result<(Preamble, String), Mishap> {
+    let lines: Vec<&str> = body.lines().skip_while(|l| l.trim().is_empty()).collect();
+
+    let (fields, rest) = match yaml_block(&lines)? {
+        Some(found) => found,
+        None => match key_value_block(&lines) {
+            Some(found) => found,
+            None => return Ok((Preamble::default(), body)),
+        },
+    };
+
+    let preamble = preamble(fields, reject_unknown)?;
+    let rest: Vec<&str> = rest
+        .iter()
+        .copied()
+        .skip_while(|l| l.trim().is_empty())
+        .collect();
+    Ok((preamble, rest.join("\n")))
+}
+
+fn yaml_block<'a>(lines: &'a [&'a str]) -> Result<Option<(Mapping, &'a [&'a str])>, Mishap> {
+    if lines.first().map(|l| l.trim_end()) != Some("---") {
+        return Ok(None);
+    }
+
+    let end = lines
+        .iter()
+        .skip(1)
+        .position(|l| matches!(l.trim_end(), "---" | "..."));
+
+    match end {
+        None => Ok(None),
+        Some(end) => {
+            let yaml = lines[1..=end].join("\n");
+            let fields = match serde_yaml::from_str::<Value>(&yaml) {
+                Ok(Value::Mapping(mapping)) => mapping,
+                Ok(Value::Null) => Mapping::new(),
+                Ok(_) => return Err(bad("front matter is not a list of fields")),
+                Err(err) => return Err(bad(&err.to_string())),
+            };
+            Ok(Some((fields, &lines[end + 2..])))
+        }
+    }
+}
+
+fn key_value_block<'a>(lines: &'a [&'a str]) -> Option<(Mapping, &'a [&'a str])> {
+    let block: Vec<&str> = lines
+        .iter()
+        .copied()
+        .take_while(|l| !l.trim().is_empty())
+        .collect();
+
+    let mut fields = Mapping::new();
+    for line in block.iter() {
+        let caps = KEY_VALUE.captures(line)?;
+        let key = caps[1].to_lowercase();
+        // Values are text, unless they look like a number or true/false
+        let value = serde_yaml::from_str::<Value>(&caps[2])
+            .ok()
+            .filter(|v| matches!(v, Value::Bool(_) | Value::Number(_)))
+            .unwrap_or_else(|| Value::String(caps[2].to_string()));
+        fields.insert(Value::String(key), value);
+    }
+
+    // Ordinary text that happens to contain a colon shouldn't be taken as a preamble
+    let has_known_field = fields
+        .keys()
+        .any(|k| k.as_str().is_some_and(|k| FIELDS.contains(&k)));
+    let is_forwarded = fields
+        .keys()
+        .next()
+        .is_some_and(|k| k.as_str() == Some("from"));
+
+    (has_known_field && !is_forwarded).then(|| (fields, &lines[block.len()..]))
+}
+
+fn preamble(fields: Mapping, reject_unknown: bool) -> Result<Preamble, Mishap> {
+    let mut preamble = Preamble::default();
+
+    for (key, value) in fields.into_iter() {
+        let key = match key.as_str() {
+            Some(key) => key.to_lowercase(),
+            None => return Err(bad("field names must be text")),
+        };
+
+        match key.as_str() {
+            "title" => preamble.title = text(&value),
+            "summary" => preamble.summary = text(&value),
+            "author" => preamble.author = text(&value),
+            "date" => {
+                let date = text(&value).ok_or_else(|| bad("date is empty"))?;
+                preamble.date = Some(date_parse(&date)?);
+            }
+            "draft" => preamble.draft = Some(flag(&value)?),
+            "tags" => preamble.tags = tags(&value),
+            _ if reject_unknown => return Err(bad(&format!("unknown field {}", key))),
+            _ if RESERVED.contains(&key.as_str()) => {
+                return Err(bad(&format!("{} can't be set", key)));
+            }
+            _ => {
+                preamble.extra.insert(key, value);
+            }
+        }
+    }
+
+    Ok(preamble)
+}
+
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(str) => Some(str.trim().to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+    .filter(|str| !str.is_empty())
+}
+
+fn flag(value: &Value) -> Result<bool, Mishap> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::Null => Ok(true),
+        other => match text(other).map(|s| s.to_lowercase()).as_deref() {
+            Some("yes" | "y" | "on" | "true") => Ok(true),
+            Some("no" | "n" | "off" | "false") => Ok(false),
+            _ => Err(bad("draft should be true or false")),
+        },
+    }
+}
+
+/// Tags as a YAML list, or as words separated by commas or spaces. A leading `#` is optional.
+fn tags(value: &Value) -> Vec<Tag> {
+    let words: Vec<String> = match value {
+        Value::Sequence(items) => items.iter().filter_map(text).collect(),
+        other => text(other)
+            .map(|s| s.split([',', ' ']).map(String::from).collect())
+            .unwrap_or_default(),
+    };
+
+    words
+        .iter()
+        .map(|w| w.trim().trim_start_matches('#'))
+        .filter(|w| !w.is_empty())
+        .map(Tag::new)
+        .collect()
+}
+
+/// A date as `2026-10-01`, `2026-10-01 14:30`, RFC 3339, or as in an email header. Times are UTC unless given.
+pub fn date_parse(str: &str) -> Result<OffsetDateTime, Mishap> {
+    let day = format_description!("[year]-[month]-[day]");
+    let minute = format_description!("[year]-[month]-[day] [hour]:[minute]");
+
+    if let Ok(dt) = OffsetDateTime::parse(str, &Rfc3339) {
+        Ok(dt)
+    } else if let Ok(dt) = PrimitiveDateTime::parse(str, &minute) {
+        Ok(dt.assume_utc())
+    } else if let Ok(date) = Date::parse(str, &day) {
+        Ok(date.midnight().assume_utc())
+    } else {
+        OffsetDateTime::parse(str, &Rfc2822).map_err(|_| bad(&format!("can't read date {}", str)))
+    }
+}
+
+fn bad(reason: &str) -> Mishap {
+    Mishap::Preamble(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_no_preamble() {
+        let body = String::from("Rex had a lovely walk.\nNote: he was muddy.\n");
+        let (preamble, rest) = split(body.clone(), false).unwrap();
+        assert_eq!(Preamble::default(), preamble);
+        assert_eq!(body, rest);
+    }
+
+    #[test]
+    fn test_key_value_preamble() {
+        let body = String::from(
+            "Title: Rex at the beach\r\ndate: 2026-10-01\r\nsummary: Sand everywhere\r\ndraft: yes\r\nauthor: Jane\r\ntags: beach, #rex\r\nmood: happy\r\n\r\nRex had a lovely walk.\r\n",
+        );
+        let (preamble, rest) = split(body, false).unwrap();
+        assert_eq!(
+            Preamble {
+                title: Some(String::from("Rex at the beach")),
+                date: Some(datetime!(2026-10-01 0:00 UTC)),
+                summary: Some(String::from("Sand everywhere")),
+                draft: Some(true),
+                author: Some(String::from("Jane")),
+                tags: vec![Tag::new("beach"), Tag::new("rex")],
+                extra: BTreeMap::from([(String::from("mood"), Value::from("happy"))]),
+            },
+            preamble
+        );
+        assert_eq!("Rex had a lovely walk.", rest);
+    }
+
+    #[test]
+    fn test_yaml_preamble() {
+        let body = String::from(
+            "---\ndate: 2026-10-01 14:30\ntags:\n  - beach\n  - rex\nweight: 1\n---\nRex had a lovely walk.\n",
+        );
+        let (preamble, rest) = split(body, false).unwrap();
+        assert_eq!(Some(datetime!(2026-10-01 14:30 UTC)), preamble.date);
+        assert_eq!(vec![Tag::new("beach"), Tag::new("rex")], preamble.tags);
+        assert_eq!(Some(&Value::from(1)), preamble.extra.get("weight"));
+        assert_eq!("Rex had a lovely walk.", rest);
+    }
+
+    #[test]
+    fn test_unknown_fields_rejected() {
+        let body = String::from("title: Beach\nmood: happy\n\nRex had a lovely walk.");
+        assert!(matches!(split(body, true), Err(Mishap::Preamble(_))));
+    }
+
+    #[test]
+    fn test_reserved_fields_rejected() {
+        let body = String::from("---\ncount: 3\n---\nRex had a lovely walk.");
+        assert!(matches!(split(body, false), Err(Mishap::Preamble(_))));
+    }
+
+    #[test]
+    fn test_forwarded_headers_are_not_a_preamble() {
+        let body = String::from(
+            "From: Jane Smith <jane@example.com>\nDate: Tue, 1 Oct 2026 at 10:00\nSubject: Rex at the beach\n\nRex ran into the sea.\n",
+        );
+        let (preamble, rest) = split(body.clone(), false).unwrap();
+        assert_eq!(Preamble::default(), preamble);
+        assert_eq!(body, rest);
+    }
+
+    #[test]
+    fn test_bad_date() {
+        let body = String::from("date: next tuesday\n\nRex had a lovely walk.");
+        assert!(matches!(split(body, false), Err(Mishap::Preamble(_))));
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(
+            datetime!(2026-10-01 14:30 +01:00),
+            date_parse("2026-10-01T14:30:00+01:00").unwrap()
+        );
+        assert_eq!(
+            datetime!(2026-10-01 13:30 UTC),
+            date_parse("Thu, 1 Oct 2026 14:30:00 +0100").unwrap()
+        );
+    }
+}
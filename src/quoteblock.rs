@@ -0,0 +1,280 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+static FORWARD_SEPARATOR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(-+\s*Forwarded message\s*-+|Begin forwarded message:)$").unwrap()
+});
+static REPLY_SEPARATOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^-+\s*Original Message\s*-+$").unwrap());
+static RULE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^_{10,}$").unwrap());
+static FORWARD_SUBJECT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(fw|fwd|wg|tr|rv)\s*:").unwrap());
+static ATTRIBUTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^(On\s.+\swrote|Am\s.+\sschrieb|Le\s.+\sa écrit|El\s.+\sescribió|Op\s.+\sgeschreven)\s?:$",
+    )
+    .unwrap()
+});
+static ATTRIBUTION_START: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(On|Am|Le|El|Op)\s").unwrap());
+
+/// Remove quoted replies and forwarded message headers from a message.
+///
+/// Quoted (`>`) lines go when they follow a line introducing them ("On ... wrote:"), along with it.
+/// Other quotes, including HTML block quotes, were meant to be in the post, so stay.
+/// Outlook doesn't quote the message being replied to, so everything after its header block goes.
+/// Forwarded messages lose their header block, and their content too unless `keep_forwarded`.
+pub fn remove(str: String, keep_forwarded: bool) -> String {
+    let lines: Vec<&str> = str.lines().collect();
+    let mut kept: Vec<String> = Vec::new();
+    let mut i = 0;
+    let mut replying = false;
+
+    while i < lines.len() {
+        let line = lines[i].trim();
+
+        if is_forward_separator(line) || is_header_block(&lines[i..], true) {
+            let rest = skip_header_block(&lines[i..]);
+            if keep_forwarded {
+                let forwarded: Vec<&str> = lines[lines.len() - rest.len()..].to_vec();
+                kept.push(remove(unquote(&forwarded), keep_forwarded));
+            }
+            break;
+        } else if is_reply_separator(line) || is_header_block(&lines[i..], false) {
+            // Everything below is the message being replied to
+            break;
+        } else if let Some(attribution_lines) = attribution(&lines[i..]) {
+            replying = true;
+            i += attribution_lines;
+        } else if line.starts_with('>') && (replying || is_quoted_attribution(&lines[i..])) {
+            replying = true;
+            i += 1;
+        } else {
+            kept.push(lines[i].to_string());
+            i += 1;
+        }
+    }
+
+    let mut cleaned = kept.join("\n").trim_end().to_string();
+    if str.ends_with('\n') && !cleaned.is_empty() {
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+/// Gmail's and Apple Mail's marker for the start of a forwarded message.
+fn is_forward_separator(line: &str) -> bool {
+    FORWARD_SEPARATOR.is_match(line)
+}
+
+/// Outlook's marker for the start of the message being replied to.
+fn is_reply_separator(line: &str) -> bool {
+    REPLY_SEPARATOR.is_match(line)
+}
+
+/// An Outlook-style `From:`, `Sent:`, `Subject:` block, optionally after a line of underscores.
+/// A forwarded message is told apart from a reply by its subject.
+fn is_header_block(lines: &[&str], forwarded: bool) -> bool {
+    let lines = match lines.first() {
+        Some(line) if RULE.is_match(line.trim()) => &lines[1..],
+        _ => lines,
+    };
+
+    let headers: Vec<(String, String)> = lines
+        .iter()
+        .map(|line| line.trim())
+        .take_while(|line| !line.is_empty())
+        .filter_map(header)
+        .collect();
+
+    let has = |name: &str| headers.iter().any(|(n, _)| n == name);
+    let is_forward = headers
+        .iter()
+        .any(|(n, v)| n == "subject" && FORWARD_SUBJECT.is_match(v));
+
+    headers.first().is_some_and(|(n, _)| n == "from")
+        && (has("sent") || has("date"))
+        && has("subject")
+        && is_forward == forwarded
+}
+
+fn header(line: &str) -> Option<(String, String)> {
+    let line = line.trim_start_matches('>').trim().replace('*', "");
+    let (name, value) = line.split_once(':')?;
+    let name = name.trim().to_lowercase();
+    let known = ["from", "sent", "date", "subject", "to", "cc", "reply-to"];
+    known
+        .contains(&name.as_str())
+        .then(|| (name, value.trim().to_string()))
+}
+
+/// The lines after a forwarding separator and/or header block, and any blank lines around it.
+fn skip_header_block<'a>(lines: &'a [&'a str]) -> &'a [&'a str] {
+    let mut rest = lines;
+    if let Some(line) = rest.first()
+        && (is_forward_separator(line.trim()) || RULE.is_match(line.trim()))
+    {
+        rest = &rest[1..];
+    }
+    while let Some(line) = rest.first()
+        && line.trim_start_matches('>').trim().is_empty()
+    {
+        rest = &rest[1..];
+    }
+    while let Some(line) = rest.first()
+        && header(line).is_some()
+    {
+        rest = &rest[1..];
+    }
+    while let Some(line) = rest.first()
+        && line.trim_start_matches('>').trim().is_empty()
+    {
+        rest = &rest[1..];
+    }
+    rest
+}
+
+/// Apple Mail quotes a forwarded message; that quoting is removed when keeping it.
+fn unquote(lines: &[&str]) -> String {
+    let all_quoted = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .all(|line| line.starts_with('>'));
+
+    let unquoted: Vec<&str> = lines
+        .iter()
+        .map(|line| match all_quoted {
+            true => line
+                .strip_prefix("> ")
+                .or_else(|| line.strip_prefix('>'))
+                .unwrap_or(line),
+            false => line,
+        })
+        .collect();
+    unquoted.join("\n")
+}
+
+/// The number of lines in an "On ... wrote:" attribution (which Gmail wraps) at the start of `lines`.
+fn attribution(lines: &[&str]) -> Option<usize> {
+    let first = lines.first()?.trim();
+    if !ATTRIBUTION_START.is_match(first) {
+        return None;
+    }
+
+    // Only an introduction if quoted text follows
+    let quote_follows = |after: usize| {
+        lines[after..]
+            .iter()
+            .find(|line| !line.trim().is_empty())
+            .is_some_and(|line| line.starts_with('>'))
+    };
+
+    if ATTRIBUTION.is_match(first) && quote_follows(1) {
+        Some(1)
+    } else if let Some(second) = lines.get(1)
+        && ATTRIBUTION.is_match(&format!("{} {}", first, second.trim()))
+        && quote_follows(2)
+    {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Apple Mail quotes the attribution along with the message being replied to.
+fn is_quoted_attribution(lines: &[&str]) -> bool {
+    let unquoted = |i: usize| {
+        lines
+            .get(i)
+            .map_or("", |line| line.trim_start_matches('>').trim())
+    };
+    let (first, second) = (unquoted(0), unquoted(1));
+    ATTRIBUTION.is_match(first) || ATTRIBUTION.is_match(&format!("{} {}", first, second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_without_quotes() {
+        let input = "Rex had a lovely walk.\n\nHe's asleep now.\n";
+        assert_eq!(input.to_string(), remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_gmail_reply() {
+        let input = "Here he is!\n\nOn Tue, 1 Oct 2026 at 10:00, Jane Smith <jane@example.com> wrote:\n\n> Any photos of Rex?\n>\n> Jane\n";
+        assert_eq!("Here he is!\n", remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_gmail_wrapped_attribution() {
+        let input = "Here he is!\n\nOn Tue, Oct 1, 2026 at 10:00 AM Jane Smith <jane@example.com>\nwrote:\n\n> Any photos of Rex?\n";
+        assert_eq!("Here he is!\n", remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_apple_mail_reply() {
+        let input = "Yes, Saturday.\n\n> On 1 Oct 2026, at 10:00, Jane Smith <jane@example.com> wrote:\n> \n> Can you bring Rex?\n";
+        assert_eq!("Yes, Saturday.\n", remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_interleaved_reply() {
+        let input = "On Tue, 1 Oct 2026, Jane wrote:\n> Where did you go?\nThe beach.\n> Was he good?\nMostly.\n";
+        assert_eq!("The beach.\nMostly.\n", remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_outlook_reply() {
+        let input = "Lovely photo.\n\n________________________________\nFrom: Jane Smith <jane@example.com>\nSent: Tuesday, 1 October 2026 10:00\nTo: Mark <mark@example.com>\nSubject: RE: Rex\n\nAny photos of Rex?\n";
+        assert_eq!("Lovely photo.\n", remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_outlook_original_message() {
+        let input = "Lovely photo.\n\n-----Original Message-----\nFrom: Jane Smith <jane@example.com>\nSent: Tuesday, 1 October 2026 10:00\nSubject: Rex\n\nAny photos?\n";
+        assert_eq!("Lovely photo.\n", remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_gmail_forward() {
+        let input = "Look at this!\n\n---------- Forwarded message ---------\nFrom: Jane Smith <jane@example.com>\nDate: Tue, 1 Oct 2026 at 10:00\nSubject: Rex at the beach\nTo: <mark@example.com>\n\n\nRex ran into the sea.\n";
+        assert_eq!("Look at this!\n", remove(input.to_string(), false));
+        assert_eq!(
+            "Look at this!\n\nRex ran into the sea.\n",
+            remove(input.to_string(), true)
+        );
+    }
+
+    #[test]
+    fn test_outlook_forward() {
+        let input = "________________________________\nFrom: Jane Smith <jane@example.com>\nSent: Tuesday, 1 October 2026 10:00\nTo: Mark <mark@example.com>\nSubject: FW: Rex at the beach\n\nRex ran into the sea.\n";
+        assert_eq!("", remove(input.to_string(), false));
+        assert_eq!("Rex ran into the sea.\n", remove(input.to_string(), true));
+    }
+
+    #[test]
+    fn test_apple_mail_forward() {
+        let input = "For the blog\n\nBegin forwarded message:\n\n> From: Jane Smith <jane@example.com>\n> Subject: Rex at the beach\n> Date: 1 October 2026 at 10:00:00 BST\n> To: Mark <mark@example.com>\n> \n> Rex ran into the sea.\n> \n> On 30 Sep 2026, at 09:00, Mark <mark@example.com> wrote:\n> \n>> Going to the beach?\n";
+        assert_eq!("For the blog\n", remove(input.to_string(), false));
+        assert_eq!(
+            "For the blog\n\nRex ran into the sea.\n",
+            remove(input.to_string(), true)
+        );
+    }
+
+    #[test]
+    fn test_standalone_quote_kept() {
+        let input = "As they say:\n\n> A tired dog is a good dog.\n\nRex agrees.\n";
+        assert_eq!(input.to_string(), remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_ordinary_text_kept() {
+        let input = "On Saturday we went to the beach.\nFrom: the car park, it's a short walk.\n";
+        assert_eq!(input.to_string(), remove(input.to_string(), false));
+    }
+}
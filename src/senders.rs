@@ -0,0 +1,160 @@
+use super::mishaps::Mishap;
+use super::settings::Settings;
+
+/// Check a sender's address against the deny list, then the allowed domains and addresses.
+///
+/// Domains match exactly, or also their subdomains with `--allow-subdomains`.
+/// A denied domain always includes its subdomains.
+/// With no rules at all, anyone may post, whatever their address looks like.
+pub fn check(settings: &Settings, address: &str) -> Result<(), Mishap> {
+    let no_allow_list =
+        settings.allowed_domains.is_empty() && settings.allowed_addresses.is_empty();
+    if no_allow_list && settings.denied_senders.is_empty() {
+        return Ok(());
+    }
+
+    let address = address.trim().to_lowercase();
+    let rejected = |rule: String| Mishap::Unauthorised {
+        sender: address.clone(),
+        rule,
+    };
+
+    let domain = match address.rsplit_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => domain,
+        _ => return Err(rejected(String::from("not a valid email address"))),
+    };
+
+    for rule in settings.denied_senders.iter() {
+        let rule = normalise(rule);
+        if rule == address || domain_matches(domain, &rule, true) {
+            return Err(rejected(format!("denied by {}", rule)));
+        }
+    }
+
+    if no_allow_list {
+        return Ok(());
+    }
+
+    let allowed_address = settings
+        .allowed_addresses
+        .iter()
+        .any(|a| normalise(a) == address);
+
+    let allowed_domain = settings
+        .allowed_domains
+        .iter()
+        .any(|d| domain_matches(domain, &normalise(d), settings.allow_subdomains));
+
+    if allowed_address || allowed_domain {
+        Ok(())
+    } else if settings.allowed_addresses.is_empty() {
+        Err(rejected(format!(
+            "domain {} is not in the allowed domains",
+            domain
+        )))
+    } else {
+        Err(rejected(String::from(
+            "not in the allowed domains or addresses",
+        )))
+    }
+}
+
+/// Allow and deny entries may be written as "example.com" or "@example.com".
+fn normalise(entry: &str) -> String {
+    entry.trim().trim_start_matches('@').to_lowercase()
+}
+
+fn domain_matches(domain: &str, rule: &str, subdomains: bool) -> bool {
+    !rule.contains('@')
+        && (domain == rule || (subdomains && domain.ends_with(&format!(".{}", rule))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(result: Result<(), Mishap>) -> Option<String> {
+        match result {
+            Err(Mishap::Unauthorised { rule, .. }) => Some(rule),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(()) => None,
+        }
+    }
+
+    #[test]
+    fn test_no_rules() {
+        let settings = Settings::for_tests(&[]);
+        assert_eq!(None, rule(check(&settings, "anyone@anywhere.com")));
+        assert_eq!(None, rule(check(&settings, "rex")));
+    }
+
+    #[test]
+    fn test_exact_domain() {
+        let settings = Settings::for_tests(&["--allowed-domains", "example.com"]);
+        assert_eq!(None, rule(check(&settings, "Alice@Example.com")));
+        assert_eq!(
+            Some(String::from(
+                "domain evilexample.com is not in the allowed domains"
+            )),
+            rule(check(&settings, "attacker@evilexample.com"))
+        );
+        assert!(rule(check(&settings, "alice@mail.example.com")).is_some());
+        assert!(rule(check(&settings, "example.com@evil.com")).is_some());
+    }
+
+    #[test]
+    fn test_subdomains() {
+        let settings =
+            Settings::for_tests(&["--allowed-domains", "example.com", "--allow-subdomains"]);
+        assert_eq!(None, rule(check(&settings, "alice@mail.example.com")));
+        assert!(rule(check(&settings, "attacker@evilexample.com")).is_some());
+    }
+
+    #[test]
+    fn test_addresses() {
+        let settings = Settings::for_tests(&[
+            "--allowed-domains",
+            "@example.com",
+            "--allowed-addresses",
+            "grandma@mail.example.net",
+        ]);
+        assert_eq!(None, rule(check(&settings, "grandma@mail.example.net")));
+        assert_eq!(None, rule(check(&settings, "alice@example.com")));
+        assert_eq!(
+            Some(String::from("not in the allowed domains or addresses")),
+            rule(check(&settings, "grandpa@mail.example.net"))
+        );
+    }
+
+    #[test]
+    fn test_deny_takes_priority() {
+        let settings = Settings::for_tests(&[
+            "--allowed-domains",
+            "example.com",
+            "--allowed-addresses",
+            "bob@example.com",
+            "--denied-senders",
+            "bob@example.com",
+            "--denied-senders",
+            "spam.example.org",
+        ]);
+        assert_eq!(None, rule(check(&settings, "alice@example.com")));
+        assert_eq!(
+            Some(String::from("denied by bob@example.com")),
+            rule(check(&settings, "bob@example.com"))
+        );
+        assert_eq!(
+            Some(String::from("denied by spam.example.org")),
+            rule(check(&settings, "x@a.spam.example.org"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_address() {
+        let settings = Settings::for_tests(&["--allowed-domains", "example.com"]);
+        assert_eq!(
+            Some(String::from("not a valid email address")),
+            rule(check(&settings, "example.com"))
+        );
+    }
+}
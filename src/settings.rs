@@ -1,7 +1,17 @@
 use clap::Parser;
+use regex::Regex;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use super::auth::AuthMethod;
+use super::command;
+use super::connection::Security;
+use super::footer;
+use super::subject;
+use super::token::{self, SenderToken};
 
 #[derive(Debug, Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = command::HELP)]
 pub struct Settings {
     /// IMAP hostname to connect to
     #[arg(long, default_value = "imap.gmail.com", env = "IMAP_HOSTNAME")]
@@ -11,13 +21,49 @@ pub struct Settings {
     #[arg(long, default_value = "993", env = "IMAP_PORT")]
     pub imap_port: u16,
 
+    /// How to secure the IMAP connection
+    #[arg(long, value_enum, default_value = "tls", env = "IMAP_SECURITY")]
+    pub imap_security: Security,
+
+    /// Extra CA certificate (PEM) to trust, e.g., for a local test server. May be repeated.
+    #[arg(long, env = "CA_CERT")]
+    pub ca_cert: Vec<PathBuf>,
+
     /// Email address (or user account) to check on the IMAP server
-    #[arg(long, env = "IMAP_USER")]
-    pub imap_user: String,
+    #[arg(
+        long,
+        env = "IMAP_USER",
+        required_unless_present_any = ["eml", "stdin", "mbox", "maildir", "pop3_hostname"]
+    )]
+    pub imap_user: Option<String>,
 
     /// Password for authentication
     #[arg(long, env = "IMAP_PASSWORD", hide_env_values = true)]
-    pub imap_password: String,
+    pub imap_password: Option<String>,
+
+    /// How to authenticate with the IMAP server
+    #[arg(long, value_enum, default_value = "password", env = "IMAP_AUTH")]
+    pub imap_auth: AuthMethod,
+
+    /// OAuth client ID (for xoauth2 or oauthbearer authentication)
+    #[arg(long, env = "OAUTH_CLIENT_ID")]
+    pub oauth_client_id: Option<String>,
+
+    /// OAuth client secret
+    #[arg(long, env = "OAUTH_CLIENT_SECRET", hide_env_values = true)]
+    pub oauth_client_secret: Option<String>,
+
+    /// OAuth refresh token, used to obtain an access token on each connection
+    #[arg(long, env = "OAUTH_REFRESH_TOKEN", hide_env_values = true)]
+    pub oauth_refresh_token: Option<String>,
+
+    /// OAuth token endpoint (e.g., https://login.microsoftonline.com/common/oauth2/v2.0/token for Microsoft 365)
+    #[arg(
+        long,
+        default_value = "https://oauth2.googleapis.com/token",
+        env = "OAUTH_TOKEN_URL"
+    )]
+    pub oauth_token_url: String,
 
     // The mailbox to read from
     #[arg(short, long, default_value = "INBOX", env = "MAILBOX")]
@@ -27,10 +73,151 @@ pub struct Settings {
     #[arg(short, long, env = "EXPURGE")]
     pub expunge: bool,
 
-    /// Allow list of sender domains. If empty, all are allowed.
+    /// Move successfully posted messages to this mailbox (instead of deleting them with --expunge)
+    #[arg(long, env = "ARCHIVE_MAILBOX")]
+    pub archive_mailbox: Option<String>,
+
+    /// Move messages that could not be posted to this mailbox
+    #[arg(long, env = "FAILED_MAILBOX")]
+    pub failed_mailbox: Option<String>,
+
+    /// Maximum number of messages to process in one run. If not set, all messages are processed.
+    #[arg(long, env = "LIMIT")]
+    pub limit: Option<u32>,
+
+    /// Read messages from this POP3 server instead of IMAP
+    #[arg(long, group = "input", env = "POP3_HOSTNAME")]
+    pub pop3_hostname: Option<String>,
+
+    /// POP3 port number
+    #[arg(long, default_value = "995", env = "POP3_PORT")]
+    pub pop3_port: u16,
+
+    /// How to secure the POP3 connection
+    #[arg(long, value_enum, default_value = "tls", env = "POP3_SECURITY")]
+    pub pop3_security: Security,
+
+    /// POP3 user account
+    #[arg(long, env = "POP3_USER")]
+    pub pop3_user: Option<String>,
+
+    /// POP3 password
+    #[arg(long, env = "POP3_PASSWORD", hide_env_values = true)]
+    pub pop3_password: Option<String>,
+
+    /// Read messages from these .eml files instead of IMAP
+    #[arg(long, group = "input")]
+    pub eml: Vec<PathBuf>,
+
+    /// Read a single message from stdin instead of IMAP (e.g., from procmail)
+    #[arg(long, group = "input")]
+    pub stdin: bool,
+
+    /// Read messages from an mbox file instead of IMAP
+    #[arg(long, group = "input")]
+    pub mbox: Option<PathBuf>,
+
+    /// Read unseen messages from a Maildir directory instead of IMAP. Posted messages are marked as seen.
+    #[arg(long, group = "input")]
+    pub maildir: Option<PathBuf>,
+
+    /// Keep running, posting messages as they arrive (via IMAP IDLE, or polling if not supported)
+    #[arg(long, env = "DAEMON", conflicts_with = "input")]
+    pub daemon: bool,
+
+    /// In daemon mode, the maximum number of seconds to wait before checking for new messages
+    #[arg(long, default_value = "300", env = "POLL_INTERVAL")]
+    pub poll_interval: u64,
+
+    /// File recording the last processed message, so mail left in the mailbox isn't posted again
+    #[arg(long, default_value = "dogpost-state.json", env = "STATE_FILE")]
+    pub state_file: PathBuf,
+
+    /// Ignore any existing state file, and rebuild it from scratch
+    #[arg(long)]
+    pub reset_state: bool,
+
+    /// Keep the content of forwarded messages in the post (their headers are always removed)
+    #[arg(long)]
+    pub keep_forwarded: bool,
+
+    /// Secret that must appear in each message: as a word in the subject, a line in the body,
+    /// or after a "+" in the address it was sent to (blog+token@example.com). May be repeated.
+    #[arg(long, env = "POSTING_TOKEN", hide_env_values = true)]
+    pub posting_token: Vec<String>,
+
+    /// Posting token for one sender, as ADDRESS=TOKEN. May be repeated.
+    #[arg(long, env = "SENDER_TOKEN", hide_env_values = true, value_parser = token::sender_token)]
+    pub sender_token: Vec<SenderToken>,
+
+    /// Trust the Authentication-Results header added by the mail server with this authserv-id
+    /// (e.g., mx.google.com), requiring a DKIM or DMARC pass for the sender's domain. May be repeated.
+    #[arg(long, env = "TRUSTED_AUTHSERV_ID")]
+    pub trusted_authserv_id: Vec<String>,
+
+    /// Verify DKIM signatures, requiring a valid signature from the sender's domain
+    #[arg(long, env = "VERIFY_DKIM")]
+    pub verify_dkim: bool,
+
+    /// DNS server (IP:port) for DKIM key lookups. Defaults to the first nameserver in /etc/resolv.conf.
+    #[arg(long, env = "DNS_SERVER")]
+    pub dns_server: Option<SocketAddr>,
+
+    /// Read DKIM keys from this file instead of DNS. Each line is a name and its TXT record,
+    /// e.g., "selector._domainkey.example.com v=DKIM1; k=rsa; p=..."
+    #[arg(long, env = "DKIM_KEY_FILE")]
+    pub dkim_key_file: Option<PathBuf>,
+
+    /// YAML file of authors, mapping each sender's email addresses to their name and slug
+    /// (for the "authors" front matter), and optionally an avatar and bio
+    #[arg(long, env = "AUTHORS_FILE")]
+    pub authors_file: Option<PathBuf>,
+
+    /// Public keys (exported by gpg, armored or not) of people who may post. Messages must be
+    /// PGP/MIME signed by one of them, with a user ID matching the From address. Needs gpg installed.
+    #[arg(long, env = "PGP_KEYRING")]
+    pub pgp_keyring: Option<PathBuf>,
+
+    /// PEM file of trusted S/MIME certificates: the senders' own, or the CAs that issued them.
+    /// Messages must be S/MIME signed with a certificate for the From address. Needs openssl installed.
+    #[arg(long, env = "SMIME_CERTS")]
+    pub smime_certs: Option<PathBuf>,
+
+    /// Reject messages that set fields other than title, date, summary, draft, author and tags at
+    /// the start of the body. By default, other fields are added to the post's front matter.
+    #[arg(long, env = "REJECT_UNKNOWN_FIELDS")]
+    pub reject_unknown_fields: bool,
+
+    /// Extra reply or forward prefix to remove from subjects (e.g., "Antw"), in addition to
+    /// Re, Fw, Fwd, AW, WG, TR, SV and RV. May be repeated.
+    #[arg(long, env = "SUBJECT_PREFIX", value_parser = subject::prefix)]
+    pub subject_prefix: Vec<Regex>,
+
+    /// Also treat a line of "--" as the start of a signature, not just the standard "-- "
+    #[arg(long, env = "LENIENT_SIGNATURE")]
+    pub lenient_signature: bool,
+
+    /// Regular expression for a footer line to remove from the end of messages, in addition to
+    /// the built-in ones such as "Sent from my iPhone". May be repeated.
+    #[arg(long, env = "FOOTER_PATTERN", value_parser = footer::footer_line)]
+    pub footer_pattern: Vec<Regex>,
+
+    /// Allow list of sender domains. If empty (and there are no allowed addresses), all are allowed.
     #[arg(long, env = "DOMAINS_ALLOW")]
     pub allowed_domains: Vec<String>,
 
+    /// Also allow senders from subdomains of the allowed domains (e.g., mail.example.com for example.com)
+    #[arg(long, env = "ALLOW_SUBDOMAINS")]
+    pub allow_subdomains: bool,
+
+    /// Allow list of individual sender addresses, in addition to the allowed domains
+    #[arg(long, env = "ADDRESSES_ALLOW")]
+    pub allowed_addresses: Vec<String>,
+
+    /// Senders (addresses or domains, including their subdomains) who may never post, even if otherwise allowed
+    #[arg(long, env = "SENDERS_DENY")]
+    pub denied_senders: Vec<String>,
+
     /// Github bearer token
     #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
     pub github_token: String,
@@ -67,3 +254,23 @@ pub struct Settings {
     #[arg(long)]
     pub dry_run: bool,
 }
+
+#[cfg(test)]
+impl Settings {
+    /// Settings for tests, reading from stdin, with the required GitHub settings and then `args`.
+    pub fn for_tests(args: &[&str]) -> Settings {
+        Settings::try_for_tests(args).unwrap()
+    }
+
+    pub fn try_for_tests(args: &[&str]) -> Result<Settings, clap::Error> {
+        let required = [
+            "dogpost",
+            "--stdin",
+            "--github-token",
+            "t",
+            "--github-repo",
+            "r",
+        ];
+        Settings::try_parse_from(required.iter().chain(args))
+    }
+}
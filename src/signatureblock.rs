@@ -2,9 +2,16 @@ use regex::Regex;
 
 /// Remove the `-- ` signature from a message.
 /// https://en.wikipedia.org/wiki/Signature_block#Standard_delimiter
-pub fn remove(str: String) -> String {
-    let pattern = Regex::new(r"(?m)--(?s).*$").unwrap();
-    pattern.replace_all(&str, "").to_string()
+///
+/// The delimiter must be a line of its own, as in RFC 3676.
+/// When `lenient`, a line of `--` without the trailing space also counts.
+pub fn remove(str: String, lenient: bool) -> String {
+    let pattern = if lenient {
+        Regex::new(r"(?m)^--[ \t]*\r?$(?s).*").unwrap()
+    } else {
+        Regex::new(r"(?m)^-- \r?$(?s).*").unwrap()
+    };
+    pattern.replace(&str, "").to_string()
 }
 
 #[cfg(test)]
@@ -15,7 +22,7 @@ mod tests {
     #[test]
     fn test_without_sig() {
         let input = "Dear Alice,\nI hope you are well\n";
-        assert_eq!(input.to_string(), remove(input.to_string()));
+        assert_eq!(input.to_string(), remove(input.to_string(), false));
     }
 
     #[test]
@@ -23,25 +30,70 @@ mod tests {
         let input = "Dear Alice,\nI hope you are well\n-- \nEddie\nyour shipboard computer";
         assert_eq!(
             "Dear Alice,\nI hope you are well\n".to_string(),
-            remove(input.to_string())
+            remove(input.to_string(), false)
+        );
+    }
+
+    #[test]
+    fn test_with_crlf_rfc3676_sig() {
+        let input = "Dear Alice,\r\nI hope you are well\r\n-- \r\nEddie\r\n";
+        assert_eq!(
+            "Dear Alice,\r\nI hope you are well\r\n".to_string(),
+            remove(input.to_string(), false)
         );
     }
 
     #[test]
     fn test_with_missing_space_sig() {
+        let input = "Dear Alice,\nI hope you are well\n--\nEddie\nyour shipboard computer";
+        assert_eq!(input.to_string(), remove(input.to_string(), false));
+    }
+
+    #[test]
+    fn test_lenient_with_missing_space_sig() {
         let input = "Dear Alice,\nI hope you are well\n--\nEddie\nyour shipboard computer";
         assert_eq!(
             "Dear Alice,\nI hope you are well\n".to_string(),
-            remove(input.to_string())
+            remove(input.to_string(), true)
         );
     }
 
     #[test]
     fn test_signature_starts_on_end_of_line() {
         let input = "Dear Alice,\nI hope you are well--\nEddie\nyour shipboard computer";
-        assert_eq!(
-            "Dear Alice,\nI hope you are well".to_string(),
-            remove(input.to_string())
-        );
+        assert_eq!(input.to_string(), remove(input.to_string(), false));
+        assert_eq!(input.to_string(), remove(input.to_string(), true));
+    }
+
+    #[test]
+    fn test_double_dashes_in_text_survive() {
+        let corpus = [
+            "Scores were 3--2 at half time.\nRex scored the winner.",
+            "Rex -- our dog -- ran off.\nWe found him.",
+            "The walk was 10--15 minutes.",
+            "Some text\n\n---\n\nA Markdown horizontal rule above.",
+            "Some text\n\n- - -\n\nMore text.",
+            "A list:\n-- nested?\n--- deeper\nEnd.",
+            "Command line: dogpost --dry-run --limit 1",
+            "Arrows --> and <-- everywhere.",
+            "Em dash — and en dash – are fine.",
+            "Ends with a dash --",
+            "--start of a line, not a signature\nmore",
+            "> -- \n> quoted signature is not ours",
+        ];
+        for input in corpus {
+            assert_eq!(
+                input.to_string(),
+                remove(input.to_string(), false),
+                "{}",
+                input
+            );
+            assert_eq!(
+                input.to_string(),
+                remove(input.to_string(), true),
+                "{}",
+                input
+            );
+        }
     }
 }
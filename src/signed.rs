@@ -0,0 +1,368 @@
+use log::debug;
+use mailparse::{MailHeader, ParsedMail};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use super::mishaps::Mishap;
+use super::settings::Settings;
+
+/// Check that a message is signed by the sender, with PGP/MIME (RFC 3156) or S/MIME (RFC 8551),
+/// if the settings give trusted keys or certificates.
+///
+/// Only the `multipart/signed` form is accepted, and only when it is the whole message,
+/// so nothing that is posted can be outside the signature.
+/// Signatures are checked with `gpg` and `openssl`, which must be installed.
+pub fn check(settings: &Settings, mail: &ParsedMail, sender: &str) -> Result<(), Mishap> {
+    if settings.pgp_keyring.is_none() && settings.smime_certs.is_none() {
+        return Ok(());
+    }
+
+    let unverified = |reason: String| Mishap::Unverified {
+        sender: sender.to_string(),
+        reason,
+    };
+
+    if mail.ctype.mimetype != "multipart/signed" {
+        return Err(unverified(format!(
+            "message is {}, not multipart/signed",
+            mail.ctype.mimetype
+        )));
+    }
+
+    let [content, signature] = mail.subparts.as_slice() else {
+        return Err(unverified(String::from(
+            "signed message should have two parts",
+        )));
+    };
+
+    let protocol = mail
+        .ctype
+        .params
+        .get("protocol")
+        .map(|p| p.to_lowercase())
+        .unwrap_or_default();
+
+    let signers = match (
+        protocol.as_str(),
+        &settings.pgp_keyring,
+        &settings.smime_certs,
+    ) {
+        ("application/pgp-signature", Some(keyring), _) => pgp(keyring, content, signature),
+        ("application/pkcs7-signature" | "application/x-pkcs7-signature", _, Some(certs)) => {
+            smime(certs, mail.raw_bytes)
+        }
+        _ => Err(format!("{} signatures are not accepted", protocol)),
+    }
+    .map_err(unverified)?;
+
+    debug!("Message signed by {:?}", signers);
+
+    if signers.iter().any(|s| s.eq_ignore_ascii_case(sender)) {
+        Ok(())
+    } else {
+        Err(unverified(format!(
+            "signed by {}, not the sender",
+            signers.join(", ")
+        )))
+    }
+}
+
+/// The headers to take the subject and date from.
+///
+/// When signatures are required, the message's own headers are outside the signature, and anyone
+/// replaying a signed message could change them. So only the headers of the signed part are used,
+/// where mail clients put a protected copy of the subject (as Thunderbird does).
+pub fn trusted_headers<'a>(settings: &Settings, mail: &'a ParsedMail<'a>) -> &'a [MailHeader<'a>] {
+    if settings.pgp_keyring.is_none() && settings.smime_certs.is_none() {
+        return &mail.headers;
+    }
+    mail.subparts
+        .first()
+        .map(|content| content.headers.as_slice())
+        .unwrap_or_default()
+}
+
+/// Verify a PGP/MIME signature, returning the email addresses of the signing key.
+///
+/// The keyring (armored or binary) is imported into a throwaway home directory each time,
+/// so only those keys are trusted, whatever else is on the machine.
+fn pgp(
+    keyring: &Path,
+    content: &ParsedMail,
+    signature: &ParsedMail,
+) -> Result<Vec<String>, String> {
+    let home = tempfile::tempdir().map_err(|err| err.to_string())?;
+    let data = home.path().join("content");
+    let sig = home.path().join("signature.asc");
+    fs::write(&data, canonical(content.raw_bytes)).map_err(|err| err.to_string())?;
+    let sig_bytes = signature.get_body_raw().map_err(|err| err.to_string())?;
+    fs::write(&sig, sig_bytes).map_err(|err| err.to_string())?;
+
+    let import = gpg(home.path(), &[OsStr::new("--import"), keyring.as_os_str()])?;
+    if !import.status.success() {
+        return Err(format!("could not read PGP keyring {}", keyring.display()));
+    }
+
+    let verify = gpg(
+        home.path(),
+        &[
+            OsStr::new("--status-fd"),
+            OsStr::new("1"),
+            OsStr::new("--trust-model"),
+            OsStr::new("always"),
+            OsStr::new("--verify"),
+            sig.as_os_str(),
+            data.as_os_str(),
+        ],
+    )?;
+    let status = String::from_utf8_lossy(&verify.stdout);
+    let fingerprint = gpg_status(&status)?;
+
+    let list = gpg(
+        home.path(),
+        &[
+            OsStr::new("--with-colons"),
+            OsStr::new("--list-keys"),
+            OsStr::new(fingerprint),
+        ],
+    )?;
+    Ok(user_ids(&String::from_utf8_lossy(&list.stdout)))
+}
+
+/// The fingerprint of a good signature, from gpg's `--status-fd` output.
+fn gpg_status(status: &str) -> Result<&str, String> {
+    let keywords: Vec<Vec<&str>> = status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] "))
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    let has = |keyword: &str| keywords.iter().any(|k| k.first() == Some(&keyword));
+
+    let valid = keywords
+        .iter()
+        .find(|k| k.first() == Some(&"VALIDSIG"))
+        .and_then(|k| k.get(1));
+
+    match valid {
+        Some(fingerprint) if has("GOODSIG") => Ok(fingerprint),
+        _ if has("BADSIG") => Err(String::from("bad PGP signature")),
+        _ if has("EXPKEYSIG") => Err(String::from("PGP key has expired")),
+        _ if has("REVKEYSIG") => Err(String::from("PGP key has been revoked")),
+        _ if has("NO_PUBKEY") => Err(String::from("signed by a PGP key not in the keyring")),
+        _ => Err(String::from("PGP signature could not be checked")),
+    }
+}
+
+/// The email addresses of the user IDs in `gpg --with-colons` output, other than revoked or expired ones.
+fn user_ids(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields[0] == "uid" && fields.len() > 9)
+        .filter(|fields| !matches!(fields[1], "r" | "e"))
+        .map(|fields| {
+            let uid = fields[9];
+            match (uid.rfind('<'), uid.rfind('>')) {
+                (Some(start), Some(end)) if start < end => uid[start + 1..end].to_string(),
+                _ => uid.trim().to_string(),
+            }
+        })
+        .collect()
+}
+
+fn gpg(home: &Path, args: &[&OsStr]) -> Result<Output, String> {
+    Command::new("gpg")
+        .arg("--homedir")
+        .arg(home)
+        .args(["--batch", "--no-tty", "--quiet"])
+        .args(args)
+        .output()
+        .map_err(|err| format!("could not run gpg: {}", err))
+}
+
+/// Verify an S/MIME signature, returning the email addresses of the signer's certificate.
+///
+/// The certificate must chain to one of the trusted certificates, which may be the
+/// signer's own certificate or a CA's.
+fn smime(certs: &Path, message: &[u8]) -> Result<Vec<String>, String> {
+    let dir = tempfile::tempdir().map_err(|err| err.to_string())?;
+    let input = dir.path().join("message.eml");
+    let signer = dir.path().join("signer.pem");
+    fs::write(&input, message).map_err(|err| err.to_string())?;
+
+    let verify = openssl(&[
+        OsStr::new("smime"),
+        OsStr::new("-verify"),
+        OsStr::new("-in"),
+        input.as_os_str(),
+        OsStr::new("-CAfile"),
+        certs.as_os_str(),
+        OsStr::new("-partial_chain"),
+        OsStr::new("-purpose"),
+        OsStr::new("smimesign"),
+        OsStr::new("-signer"),
+        signer.as_os_str(),
+        OsStr::new("-out"),
+        dir.path().join("content").as_os_str(),
+    ])?;
+    if !verify.status.success() {
+        let stderr = String::from_utf8_lossy(&verify.stderr);
+        let detail = stderr
+            .lines()
+            .filter_map(|line| line.rsplit(':').next())
+            .map(str::trim)
+            .rfind(|detail| !detail.is_empty())
+            .unwrap_or("verification failed");
+        return Err(format!("S/MIME signature not valid: {}", detail));
+    }
+
+    let emails = openssl(&[
+        OsStr::new("x509"),
+        OsStr::new("-in"),
+        signer.as_os_str(),
+        OsStr::new("-noout"),
+        OsStr::new("-email"),
+    ])?;
+    Ok(String::from_utf8_lossy(&emails.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+fn openssl(args: &[&OsStr]) -> Result<Output, String> {
+    Command::new("openssl")
+        .args(args)
+        .output()
+        .map_err(|err| format!("could not run openssl: {}", err))
+}
+
+/// PGP/MIME signs the content with CRLF line endings, which a message saved to disk may have lost.
+fn canonical(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for (index, byte) in bytes.iter().enumerate() {
+        if *byte == b'\n' && (index == 0 || bytes[index - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(*byte);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/signed/");
+
+    fn settings() -> Settings {
+        Settings::for_tests(&[
+            "--pgp-keyring",
+            &format!("{}pgp-keyring.asc", FIXTURES),
+            "--smime-certs",
+            &format!("{}smime-certs.pem", FIXTURES),
+        ])
+    }
+
+    fn fixture(name: &str) -> String {
+        fs::read_to_string(format!("{}{}", FIXTURES, name)).unwrap()
+    }
+
+    fn reason(eml: &str, sender: &str) -> Option<String> {
+        let mail = mailparse::parse_mail(eml.as_bytes()).unwrap();
+        match check(&settings(), &mail, sender) {
+            Ok(()) => None,
+            Err(Mishap::Unverified { reason, .. }) => Some(reason),
+            Err(other) => panic!("Unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pgp() {
+        let eml = fixture("pgp.eml");
+        assert_eq!(None, reason(&eml, "rex@example.com"));
+        assert_eq!(None, reason(&eml.replace("\r\n", "\n"), "Rex@Example.com"));
+        assert_eq!(
+            Some(String::from("bad PGP signature")),
+            reason(&eml.replace("lovely", "muddy"), "rex@example.com")
+        );
+        assert_eq!(
+            Some(String::from("signed by rex@example.com, not the sender")),
+            reason(&eml, "mallory@example.com")
+        );
+        assert_eq!(
+            Some(String::from("signed by a PGP key not in the keyring")),
+            reason(&fixture("pgp-stranger.eml"), "mallory@example.com")
+        );
+    }
+
+    #[test]
+    fn test_smime() {
+        let eml = fixture("smime.eml");
+        assert_eq!(None, reason(&eml, "rex@example.com"));
+        assert_eq!(None, reason(&eml.replace("\r\n", "\n"), "rex@example.com"));
+        assert!(
+            reason(&eml.replace("lovely", "muddy"), "rex@example.com")
+                .unwrap()
+                .starts_with("S/MIME signature not valid")
+        );
+        assert_eq!(
+            Some(String::from("signed by rex@example.com, not the sender")),
+            reason(&eml, "mallory@example.com")
+        );
+        assert!(
+            reason(&fixture("smime-stranger.eml"), "mallory@example.com")
+                .unwrap()
+                .starts_with("S/MIME signature not valid")
+        );
+    }
+
+    #[test]
+    fn test_unsigned() {
+        let eml = "From: rex@example.com\r\nSubject: Walk\r\n\r\nHello";
+        assert_eq!(
+            Some(String::from("message is text/plain, not multipart/signed")),
+            reason(eml, "rex@example.com")
+        );
+    }
+
+    #[test]
+    fn test_not_required() {
+        let settings = Settings::for_tests(&[]);
+        let mail = mailparse::parse_mail(b"Subject: Walk\r\n\r\nHello").unwrap();
+        assert!(check(&settings, &mail, "rex@example.com").is_ok());
+    }
+
+    #[test]
+    fn test_gpg_status() {
+        let status = "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG DC45B19DC92880C0 Rex <rex@example.com>\n[GNUPG:] VALIDSIG 41023118DC991ABDA5003699DC45B19DC92880C0 2026-10-17 1792053380 0 4 0 22 8 00 41023118DC991ABDA5003699DC45B19DC92880C0\n";
+        assert_eq!(
+            Ok("41023118DC991ABDA5003699DC45B19DC92880C0"),
+            gpg_status(status)
+        );
+        assert_eq!(
+            Err(String::from("PGP key has expired")),
+            gpg_status("[GNUPG:] EXPKEYSIG DC45B19DC92880C0 Rex <rex@example.com>\n")
+        );
+    }
+
+    #[test]
+    fn test_user_ids() {
+        let listing = "pub:-:255:22:DC45B19DC92880C0:1792053380:::-:::scSC::::::23::0:\nuid:-::::1792053380::AB12::Rex <rex@example.com>::::::::::0:\nuid:r::::1792053380::CD34::Rex <old@example.com>::::::::::0:\nuid:-::::1792053380::EF56::rex@example.org::::::::::0:\n";
+        assert_eq!(
+            vec![
+                String::from("rex@example.com"),
+                String::from("rex@example.org")
+            ],
+            user_ids(listing)
+        );
+    }
+
+    #[test]
+    fn test_canonical() {
+        assert_eq!(b"a\r\nb\r\n\r\nc".to_vec(), canonical(b"a\nb\r\n\nc"));
+    }
+}
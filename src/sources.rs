@@ -0,0 +1,358 @@
+use log::debug;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::mishaps::Mishap;
+use super::pop3::Pop3;
+use super::settings::Settings;
+use super::state::State;
+
+/// A MIME message, and where it came from.
+pub struct Message {
+    pub origin: Origin,
+
+    /// The mime content of the message (including header), as raw bytes
+    pub mime: Vec<u8>,
+}
+
+/// Where a message was read from, so the source can acknowledge it after processing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    /// A UID in the IMAP mailbox
+    Imap(u32),
+    /// A single file, such as an `.eml` file or a message in a Maildir
+    File(PathBuf),
+    /// The nth message in an mbox file
+    Mbox(usize),
+    /// A message number and unique ID in a POP3 mailbox
+    Pop3 {
+        number: u32,
+        uidl: String,
+    },
+    Stdin,
+}
+
+/// Somewhere we can read messages to post.
+pub trait Source {
+    /// Fetch the messages waiting to be posted, oldest first.
+    fn fetch(&mut self, state: &mut State, limit: Option<u32>) -> Result<Vec<Message>, Mishap>;
+
+    /// Called once a message has been processed.
+    fn done(&mut self, message: &Message, posted: bool) -> Result<(), Mishap>;
+
+    fn close(&mut self) -> Result<(), Mishap> {
+        Ok(())
+    }
+}
+
+/// The non-IMAP source selected in the settings, if any.
+pub fn from_settings(settings: &Settings) -> Result<Option<Box<dyn Source>>, Mishap> {
+    if let Some(hostname) = &settings.pop3_hostname {
+        Ok(Some(Box::new(Pop3::open(settings, hostname)?)))
+    } else {
+        Ok(files_from_settings(settings))
+    }
+}
+
+fn files_from_settings(settings: &Settings) -> Option<Box<dyn Source>> {
+    if !settings.eml.is_empty() {
+        Some(Box::new(EmlFiles(settings.eml.clone())))
+    } else if settings.stdin {
+        Some(Box::new(Stdin))
+    } else if let Some(path) = &settings.mbox {
+        Some(Box::new(Mbox(path.clone())))
+    } else {
+        settings.maildir.as_ref().map(|dir| {
+            Box::new(Maildir {
+                dir: dir.clone(),
+                expunge: settings.expunge,
+            }) as Box<dyn Source>
+        })
+    }
+}
+
+fn limited<T>(mut items: Vec<T>, limit: Option<u32>) -> Vec<T> {
+    if let Some(limit) = limit {
+        items.truncate(limit as usize);
+    }
+    items
+}
+
+/// One or more `.eml` files, each holding a single message.
+pub struct EmlFiles(Vec<PathBuf>);
+
+impl Source for EmlFiles {
+    fn fetch(&mut self, _state: &mut State, limit: Option<u32>) -> Result<Vec<Message>, Mishap> {
+        let mut messages = Vec::new();
+        for path in self.0.iter() {
+            debug!("Reading {}", path.display());
+            messages.push(Message {
+                origin: Origin::File(path.clone()),
+                mime: fs::read(path)?,
+            });
+        }
+        Ok(limited(messages, limit))
+    }
+
+    fn done(&mut self, _message: &Message, _posted: bool) -> Result<(), Mishap> {
+        Ok(())
+    }
+}
+
+/// A single message piped to us, e.g., from procmail or fetchmail.
+pub struct Stdin;
+
+impl Source for Stdin {
+    fn fetch(&mut self, _state: &mut State, _limit: Option<u32>) -> Result<Vec<Message>, Mishap> {
+        let mut mime = Vec::new();
+        std::io::stdin().read_to_end(&mut mime)?;
+        Ok(vec![Message {
+            origin: Origin::Stdin,
+            mime,
+        }])
+    }
+
+    fn done(&mut self, _message: &Message, _posted: bool) -> Result<(), Mishap> {
+        Ok(())
+    }
+}
+
+/// An mbox file. The file is only read, never modified.
+pub struct Mbox(PathBuf);
+
+impl Source for Mbox {
+    fn fetch(&mut self, _state: &mut State, limit: Option<u32>) -> Result<Vec<Message>, Mishap> {
+        debug!("Reading mbox {}", self.0.display());
+        let content = fs::read(&self.0)?;
+        let messages = split_mbox(&content)
+            .into_iter()
+            .enumerate()
+            .map(|(index, mime)| Message {
+                origin: Origin::Mbox(index),
+                mime,
+            })
+            .collect();
+        Ok(limited(messages, limit))
+    }
+
+    fn done(&mut self, _message: &Message, _posted: bool) -> Result<(), Mishap> {
+        Ok(())
+    }
+}
+
+/// Split an mbox into messages. Each message starts with a "From " line,
+/// and lines in the body that would look like one are escaped as ">From " (mboxrd).
+fn split_mbox(content: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<&[u8]>> = None;
+    let mut previous_blank = true;
+
+    for line in content.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if previous_blank && line.starts_with(b"From ") {
+            if let Some(lines) = current.take() {
+                messages.push(join_mbox_lines(lines));
+            }
+            current = Some(Vec::new());
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+        }
+        previous_blank = line.is_empty();
+    }
+
+    if let Some(lines) = current {
+        messages.push(join_mbox_lines(lines));
+    }
+
+    messages
+}
+
+fn join_mbox_lines(mut lines: Vec<&[u8]>) -> Vec<u8> {
+    // The trailing newline of the file, and the blank line before the next "From ",
+    // belong to the mbox format, not the message
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut mime = Vec::new();
+    for line in lines {
+        let unquoted = line.iter().position(|&b| b != b'>').unwrap_or(line.len());
+        if unquoted > 0 && line[unquoted..].starts_with(b"From ") {
+            mime.extend_from_slice(&line[1..]);
+        } else {
+            mime.extend_from_slice(line);
+        }
+        mime.extend_from_slice(b"\r\n");
+    }
+    mime
+}
+
+/// A Maildir. Unseen messages are read from `new` and `cur`.
+/// Posted messages are marked as seen (or deleted, if expunging); failed messages are left alone.
+pub struct Maildir {
+    dir: PathBuf,
+    expunge: bool,
+}
+
+impl Source for Maildir {
+    fn fetch(&mut self, _state: &mut State, limit: Option<u32>) -> Result<Vec<Message>, Mishap> {
+        let mut paths = Vec::new();
+        for sub_dir in ["new", "cur"] {
+            for entry in fs::read_dir(self.dir.join(sub_dir))? {
+                let path = entry?.path();
+                if path.is_file() && !is_seen(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+
+        // Maildir file names start with the delivery time
+        paths.sort_by_key(|p| p.file_name().map(|n| n.to_owned()));
+
+        let mut messages = Vec::new();
+        for path in limited(paths, limit) {
+            debug!("Reading {}", path.display());
+            messages.push(Message {
+                mime: fs::read(&path)?,
+                origin: Origin::File(path),
+            });
+        }
+        Ok(messages)
+    }
+
+    fn done(&mut self, message: &Message, posted: bool) -> Result<(), Mishap> {
+        match &message.origin {
+            Origin::File(path) if posted && self.expunge => Ok(fs::remove_file(path)?),
+            Origin::File(path) if posted => Ok(fs::rename(path, self.seen_path(path))?),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Maildir {
+    /// Where a message goes once read: in `cur`, with the "S" (seen) flag in the info section.
+    fn seen_path(&self, path: &Path) -> PathBuf {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let seen_name = match name.split_once(":2,") {
+            Some((unique, flags)) => {
+                let mut flags: Vec<char> = flags.chars().chain(['S']).collect();
+                flags.sort_unstable();
+                flags.dedup();
+                format!("{}:2,{}", unique, flags.into_iter().collect::<String>())
+            }
+            None => format!("{}:2,S", name),
+        };
+
+        self.dir.join("cur").join(seen_name)
+    }
+}
+
+fn is_seen(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.split_once(":2,"))
+        .map(|(_, flags)| flags.contains('S'))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authors::Directory;
+    use crate::email;
+    use clap::Parser;
+    use pretty_assertions::assert_eq;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/email");
+
+    #[test]
+    fn test_split_mbox() {
+        let mbox = b"From alice@example.com Sat Oct  3 10:15:00 2026\n\
+                    Subject: One\n\
+                    \n\
+                    First\n\
+                    >From the beach\n\
+                    \n\
+                    From bob@example.com Sat Oct  3 11:15:00 2026\n\
+                    Subject: Two\n\
+                    \n\
+                    Second\n";
+
+        assert_eq!(
+            vec![
+                b"Subject: One\r\n\r\nFirst\r\nFrom the beach\r\n".to_vec(),
+                b"Subject: Two\r\n\r\nSecond\r\n".to_vec()
+            ],
+            split_mbox(mbox)
+        );
+    }
+
+    #[test]
+    fn test_maildir_marks_posted_messages_seen() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub_dir in ["new", "cur", "tmp"] {
+            fs::create_dir(dir.path().join(sub_dir)).unwrap();
+        }
+        fs::write(dir.path().join("new/1000.a.host"), "Subject: new\r\n\r\n").unwrap();
+        fs::write(
+            dir.path().join("cur/900.b.host:2,"),
+            "Subject: unseen\r\n\r\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("cur/800.c.host:2,S"),
+            "Subject: seen\r\n\r\n",
+        )
+        .unwrap();
+
+        let mut maildir = Maildir {
+            dir: dir.path().to_path_buf(),
+            expunge: false,
+        };
+        let messages = maildir.fetch(&mut State::default(), None).unwrap();
+        assert_eq!(2, messages.len());
+
+        for message in messages.iter() {
+            maildir.done(message, true).unwrap();
+        }
+        assert!(dir.path().join("cur/1000.a.host:2,S").exists());
+        assert!(dir.path().join("cur/900.b.host:2,S").exists());
+        assert!(
+            maildir
+                .fetch(&mut State::default(), None)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_eml_through_pipeline() {
+        let path = PathBuf::from(format!("{}/plain.eml", FIXTURES));
+        let settings = Settings::parse_from([
+            "dogpost",
+            &format!("--eml={}", path.display()),
+            "--github-token=gh",
+            "--github-repo=user/repo",
+        ]);
+        let working_dir = tempfile::tempdir().unwrap();
+
+        let mut source = from_settings(&settings).unwrap().unwrap();
+        let messages = source.fetch(&mut State::default(), None).unwrap();
+        assert_eq!(Origin::File(path), messages[0].origin);
+
+        let info = email::parse(&messages[0].mime)
+            .and_then(|mail| {
+                email::extract(&settings, &Directory::default(), working_dir.path(), mail)
+            })
+            .unwrap();
+
+        assert_eq!("Walk in the park", info.title);
+        assert_eq!("Alice Example", info.author);
+        assert_eq!(Some("Rex chased a squirrel.".to_string()), info.content);
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use super::mishaps::Mishap;
+use super::sources::Origin;
+
+/// What we remember between runs, so a message left in the mailbox is only posted once.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// The UIDVALIDITY of the mailbox when `last_uid` was recorded. If the server changes this,
+    /// the UIDs we know about are meaningless.
+    pub uid_validity: Option<u32>,
+
+    /// The highest UID we've processed.
+    pub last_uid: u32,
+
+    /// UIDs at or below `last_uid` that failed to post, and are tried again on the next fetch.
+    #[serde(default)]
+    pub failed_uids: BTreeSet<u32>,
+
+    /// The UIDLs of messages we've processed that are still on the POP3 server.
+    #[serde(default)]
+    pub pop3_uidls: BTreeSet<String>,
+
+    /// The Message-ID of every message we've posted, from any source.
+    #[serde(default)]
+    pub message_ids: BTreeSet<String>,
+}
+
+impl State {
+    /// Read the state from disk. A missing file is the same as starting from scratch.
+    pub fn load(path: &Path) -> Result<State, Mishap> {
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(State::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Mishap> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Remember that we've processed a message. A message that failed is tried again next time.
+    pub fn record(&mut self, origin: &Origin, posted: bool) {
+        match origin {
+            Origin::Imap(uid) => {
+                self.last_uid = self.last_uid.max(*uid);
+                if posted {
+                    self.failed_uids.remove(uid);
+                } else {
+                    self.failed_uids.insert(*uid);
+                }
+            }
+            Origin::Pop3 { uidl, .. } if posted => {
+                self.pop3_uidls.insert(uidl.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// Remember that we've posted a message with the given Message-ID.
+    pub fn record_posted(&mut self, message_id: &str) {
+        self.message_ids.insert(message_id.to_string());
+    }
+
+    pub fn already_posted(&self, message_id: &str) -> bool {
+        self.message_ids.contains(message_id)
+    }
+
+    /// Forget everything we know if the mailbox UIDs have been reset by the server.
+    pub fn validate(&mut self, uid_validity: Option<u32>) {
+        if self.uid_validity != uid_validity {
+            log::info!(
+                "UIDVALIDITY changed from {:?} to {:?}: rebuilding state",
+                self.uid_validity,
+                uid_validity
+            );
+            self.uid_validity = uid_validity;
+            self.last_uid = 0;
+            self.failed_uids.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_missing_file_is_fresh_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::load(&dir.path().join("state.json")).unwrap();
+        assert_eq!(None, state.uid_validity);
+        assert_eq!(0, state.last_uid);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut state = State {
+            uid_validity: Some(42),
+            last_uid: 7,
+            ..State::default()
+        };
+        state.record_posted("<walk-1@example.com>");
+        state.save(&path).unwrap();
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(Some(42), loaded.uid_validity);
+        assert_eq!(7, loaded.last_uid);
+        assert!(loaded.already_posted("<walk-1@example.com>"));
+        assert!(!loaded.already_posted("<walk-2@example.com>"));
+    }
+
+    #[test]
+    fn test_reads_state_without_message_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, r#"{"uid_validity": 42, "last_uid": 7}"#).unwrap();
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(7, loaded.last_uid);
+        assert!(loaded.message_ids.is_empty());
+    }
+
+    #[test]
+    fn test_failed_uids_are_retried() {
+        let mut state = State::default();
+        state.record(&Origin::Imap(3), false);
+        state.record(&Origin::Imap(4), true);
+        assert_eq!(4, state.last_uid);
+        assert_eq!(BTreeSet::from([3]), state.failed_uids);
+
+        state.record(&Origin::Imap(3), true);
+        assert_eq!(4, state.last_uid);
+        assert!(state.failed_uids.is_empty());
+    }
+
+    #[test]
+    fn test_failed_uidls_are_retried() {
+        let mut state = State::default();
+        let message = |uidl: &str| Origin::Pop3 {
+            number: 1,
+            uidl: uidl.to_string(),
+        };
+        state.record(&message("aaa"), false);
+        state.record(&message("bbb"), true);
+        assert_eq!(BTreeSet::from([String::from("bbb")]), state.pop3_uidls);
+    }
+
+    #[test]
+    fn test_uid_validity_change_resets() {
+        let mut state = State {
+            uid_validity: Some(42),
+            last_uid: 7,
+            ..State::default()
+        };
+
+        state.validate(Some(42));
+        assert_eq!(7, state.last_uid);
+
+        state.validate(Some(43));
+        assert_eq!(Some(43), state.uid_validity);
+        assert_eq!(0, state.last_uid);
+    }
+}
@@ -0,0 +1,107 @@
+use regex::{Regex, RegexBuilder};
+use std::sync::LazyLock;
+
+/// Reply and forward prefixes: English, German (AW, WG), French (TR), Scandinavian (SV) and Spanish (RV).
+pub const DEFAULT_PREFIXES: &[&str] = &["Re", "Fw", "Fwd", "AW", "WG", "TR", "SV", "RV"];
+
+static DEFAULT_REPLY: LazyLock<Regex> = LazyLock::new(|| {
+    let prefixes: Vec<String> = DEFAULT_PREFIXES.iter().map(|p| regex::escape(p)).collect();
+    reply(&prefixes.join("|")).unwrap()
+});
+static LIST_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\[[^\]]*\]\s*").unwrap());
+
+/// Remove reply and forward prefixes, and mailing list tags, from the start of a subject.
+///
+/// "Fwd: Re: [family] Walk in the park" becomes "Walk in the park".
+/// `extra_prefixes` are added to the defaults, and come from `prefix`.
+pub fn normalise(subject: &str, extra_prefixes: &[Regex]) -> String {
+    let mut subject = subject.trim();
+    while let Some(found) = DEFAULT_REPLY
+        .find(subject)
+        .or_else(|| extra_prefixes.iter().find_map(|p| p.find(subject)))
+        .or_else(|| LIST_TAG.find(subject))
+    {
+        subject = subject[found.end()..].trim_start();
+    }
+    subject.to_string()
+}
+
+/// An extra prefix, matched as a plain word, ignoring case.
+///
+/// Used to build the patterns given in the settings when they are parsed.
+pub fn prefix(word: &str) -> Result<Regex, regex::Error> {
+    reply(&regex::escape(word.trim()))
+}
+
+/// A prefix may carry a count, as in "Re[2]:" or "Re(2):", and some clients use a full-width colon.
+fn reply(alternatives: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&format!(
+        r"^({})\s*(\[\d+\]|\(\d+\))?\s*[:：]\s*",
+        alternatives
+    ))
+    .case_insensitive(true)
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalise_plain() {
+        assert_eq!("Walk in the park", normalise("Walk in the park", &[]));
+    }
+
+    #[test]
+    fn test_normalise_reply_and_forward() {
+        assert_eq!(
+            "Walk in the park",
+            normalise("Fwd: Re: Walk in the park", &[])
+        );
+        assert_eq!(
+            "Walk in the park",
+            normalise("RE: FW: Walk in the park", &[])
+        );
+    }
+
+    #[test]
+    fn test_normalise_other_languages() {
+        for subject in [
+            "AW: Walk",
+            "WG: Walk",
+            "TR: Walk",
+            "SV: Walk",
+            "RV: Walk",
+            "Re[2]: Walk",
+            "Re(3) : Walk",
+            "Re： Walk",
+        ] {
+            assert_eq!("Walk", normalise(subject, &[]), "{}", subject);
+        }
+    }
+
+    #[test]
+    fn test_normalise_list_tags() {
+        assert_eq!("Walk", normalise("[family] Walk", &[]));
+        assert_eq!("Walk", normalise("Re: [family] Fwd: [dogs] Walk", &[]));
+    }
+
+    #[test]
+    fn test_normalise_keeps_words_that_look_like_prefixes() {
+        assert_eq!("Reservoir walk", normalise("Reservoir walk", &[]));
+        assert_eq!("Trip: the beach", normalise("Trip: the beach", &[]));
+        assert_eq!("Walk [photos]", normalise("Walk [photos]", &[]));
+    }
+
+    #[test]
+    fn test_normalise_extra_prefixes() {
+        let extra = vec![prefix("Antw").unwrap(), prefix(" VS ").unwrap()];
+        assert_eq!("Walk", normalise("Antw: VS: Walk", &extra));
+        assert_eq!("Antw: Walk", normalise("Antw: Walk", &[]));
+    }
+
+    #[test]
+    fn test_normalise_keeps_hashtags() {
+        assert_eq!("Walk #park", normalise("Re: Walk #park", &[]));
+    }
+}
@@ -0,0 +1,269 @@
+use mailparse::{MailAddr, MailHeaderMap, ParsedMail, addrparse};
+
+use super::mishaps::Mishap;
+use super::settings::Settings;
+
+/// Headers that may hold the address the message was sent to.
+const RECIPIENT_HEADERS: &[&str] = &["To", "Cc", "Delivered-To", "X-Original-To", "Envelope-To"];
+
+/// A posting token for one sender, from `--sender-token ADDRESS=TOKEN`.
+#[derive(Debug, Clone)]
+pub struct SenderToken {
+    pub address: String,
+    pub token: String,
+}
+
+/// Used to check `--sender-token` when the settings are parsed.
+pub fn sender_token(entry: &str) -> Result<SenderToken, String> {
+    match entry.split_once('=') {
+        Some((address, token)) if !address.trim().is_empty() && !token.is_empty() => {
+            Ok(SenderToken {
+                address: address.trim().to_string(),
+                token: token.to_string(),
+            })
+        }
+        _ => Err(String::from("expected ADDRESS=TOKEN")),
+    }
+}
+
+/// Require a posting token, if any are configured, and remove it from the subject and body.
+///
+/// The token may be a word in the subject, the `+` part of the address the message was
+/// sent to (`blog+token@example.com`), or a line of its own in the body.
+/// Valid tokens are the global ones, plus any for the sender.
+pub fn check(
+    settings: &Settings,
+    mail: &ParsedMail,
+    sender: Option<&str>,
+    subject: Option<String>,
+    body: Option<String>,
+) -> Result<(Option<String>, Option<String>), Mishap> {
+    if settings.posting_token.is_empty() && settings.sender_token.is_empty() {
+        return Ok((subject, body));
+    }
+
+    let tokens = tokens(settings, sender);
+
+    let (subject, in_subject) = match subject {
+        Some(subject) => {
+            let (subject, found) = strip_words(&subject, &tokens);
+            (Some(subject), found)
+        }
+        None => (None, false),
+    };
+
+    let (body, in_body) = match body {
+        Some(body) => {
+            let (body, found) = strip_lines(&body, &tokens);
+            (Some(body), found)
+        }
+        None => (None, false),
+    };
+
+    let in_address = plus_addresses(mail)
+        .iter()
+        .any(|detail| matches_any(detail, &tokens));
+
+    if in_subject || in_body || in_address {
+        Ok((subject, body))
+    } else {
+        Err(Mishap::MissingToken)
+    }
+}
+
+fn tokens(settings: &Settings, sender: Option<&str>) -> Vec<String> {
+    let for_sender = settings
+        .sender_token
+        .iter()
+        .filter(|entry| sender.is_some_and(|s| s.trim().eq_ignore_ascii_case(&entry.address)))
+        .map(|entry| entry.token.clone());
+
+    settings
+        .posting_token
+        .iter()
+        .cloned()
+        .chain(for_sender)
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn strip_words(subject: &str, tokens: &[String]) -> (String, bool) {
+    let mut found = false;
+    let words: Vec<&str> = subject
+        .split_whitespace()
+        .filter(|word| {
+            let is_token = matches_any(word, tokens);
+            found |= is_token;
+            !is_token
+        })
+        .collect();
+    (words.join(" "), found)
+}
+
+fn strip_lines(body: &str, tokens: &[String]) -> (String, bool) {
+    let mut found = false;
+    let lines: Vec<&str> = body
+        .lines()
+        .filter(|line| {
+            let is_token = matches_any(line.trim(), tokens);
+            found |= is_token;
+            !is_token
+        })
+        .collect();
+    (lines.join("\n"), found)
+}
+
+/// The part after the `+` in each recipient's address.
+fn plus_addresses(mail: &ParsedMail) -> Vec<String> {
+    let mut details = Vec::new();
+    for header in RECIPIENT_HEADERS {
+        for value in mail.headers.get_all_values(header) {
+            let addresses: Vec<String> = match addrparse(&value) {
+                Ok(list) => list
+                    .iter()
+                    .flat_map(|addr| match addr {
+                        MailAddr::Single(info) => vec![info.addr.clone()],
+                        MailAddr::Group(group) => {
+                            group.addrs.iter().map(|i| i.addr.clone()).collect()
+                        }
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            for address in addresses {
+                if let Some((local, _domain)) = address.rsplit_once('@')
+                    && let Some((_, detail)) = local.split_once('+')
+                {
+                    details.push(detail.to_string());
+                }
+            }
+        }
+    }
+    details
+}
+
+/// Compare with every token, taking the same time whichever (if any) matches.
+fn matches_any(candidate: &str, tokens: &[String]) -> bool {
+    tokens.iter().fold(false, |found, token| {
+        found | constant_time_eq(candidate, token)
+    })
+}
+
+/// Equal strings, compared without stopping at the first difference.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let differences = a
+        .iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    differences == 0 && a.len() == b.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn run(settings: &Settings, mime: &str) -> Result<(Option<String>, Option<String>), Mishap> {
+        let mail = mailparse::parse_mail(mime.as_bytes()).unwrap();
+        let subject = mail.headers.get_first_value("Subject");
+        let body = mail.get_body().ok();
+        check(settings, &mail, Some("alice@example.com"), subject, body)
+    }
+
+    #[test]
+    fn test_no_tokens_configured() {
+        let settings = Settings::for_tests(&[]);
+        let result = run(&settings, "Subject: Walk\r\n\r\nHello");
+        assert_eq!(
+            (Some(String::from("Walk")), Some(String::from("Hello"))),
+            result.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_token_in_subject() {
+        let settings = Settings::for_tests(&["--posting-token", "s3cret"]);
+        let result = run(&settings, "Subject: Walk s3cret in the park\r\n\r\nHello");
+        assert_eq!(
+            (
+                Some(String::from("Walk in the park")),
+                Some(String::from("Hello"))
+            ),
+            result.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_token_in_body() {
+        let settings = Settings::for_tests(&["--posting-token", "s3cret"]);
+        let result = run(&settings, "Subject: Walk\r\n\r\n  s3cret \r\nHello\r\n");
+        assert_eq!(
+            (Some(String::from("Walk")), Some(String::from("Hello"))),
+            result.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_token_in_plus_address() {
+        let settings = Settings::for_tests(&["--posting-token", "s3cret"]);
+        let result = run(
+            &settings,
+            "To: Blog <blog+s3cret@example.org>\r\nSubject: Walk\r\n\r\nHello",
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_missing_or_wrong_token() {
+        let settings = Settings::for_tests(&["--posting-token", "s3cret"]);
+        for mime in [
+            "Subject: Walk\r\n\r\nHello",
+            "Subject: Walk s3cre\r\n\r\nHello s3cret",
+            "To: blog+s3cret2@example.org\r\nSubject: Walk\r\n\r\nHello",
+        ] {
+            assert!(
+                matches!(run(&settings, mime), Err(Mishap::MissingToken)),
+                "{}",
+                mime
+            );
+        }
+    }
+
+    #[test]
+    fn test_several_tokens() {
+        let settings = Settings::for_tests(&["--posting-token", "old", "--posting-token", "new"]);
+        assert!(run(&settings, "Subject: Walk old\r\n\r\nHello").is_ok());
+        assert!(run(&settings, "Subject: Walk new\r\n\r\nHello").is_ok());
+    }
+
+    #[test]
+    fn test_sender_tokens() {
+        let settings = Settings::for_tests(&[
+            "--sender-token",
+            "Alice@example.com=alices",
+            "--sender-token",
+            "bob@example.com=bobs",
+        ]);
+        assert!(run(&settings, "Subject: Walk alices\r\n\r\nHello").is_ok());
+        assert!(matches!(
+            run(&settings, "Subject: Walk bobs\r\n\r\nHello"),
+            Err(Mishap::MissingToken)
+        ));
+    }
+
+    #[test]
+    fn test_bad_sender_token() {
+        assert!(sender_token("alice@example.com=").is_err());
+        assert!(sender_token("alices").is_err());
+        assert!(Settings::try_for_tests(&["--sender-token", "alices"]).is_err());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("s3cret", "s3cret"));
+        assert!(!constant_time_eq("s3cret", "s3cre"));
+        assert!(!constant_time_eq("s3cret", "s3creT"));
+        assert!(!constant_time_eq("", "s3cret"));
+    }
+}